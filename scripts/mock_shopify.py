@@ -0,0 +1,292 @@
+"""
+A small stand-in for the Shopify admin GraphQL API so the fixer can be run without touching a live
+store. Point the fixer at it by setting "admin_base_url" in config.json, e.g.
+
+    "admin_base_url": "http://localhost:8080"
+
+Product variants are served from a JSON file holding a list of `productVariants` nodes in the same
+shape the fixer queries for, both paged and by ID. Every mutation is accepted and echoed back with no userErrors, and each
+request body is printed so runs can be inspected.
+
+Every response reports its cost in `extensions.cost` from a simulated leaky bucket. Requests that
+cost more than the bucket holds are rejected with a THROTTLED error, like Shopify does.
+
+Pass `--fail-rate` to answer that share of requests with a 503 and a `Retry-After` header, to see
+how the fixer retries. Pass `--reject-skus` to answer price updates for those SKUs with userErrors.
+
+Bulk operations finish as soon as they are started. Their result is served from /bulk/result.jsonl,
+either from the file given with `--bulk-result` or built from the variants file. Pass `--bulk-running`
+to keep them running forever instead, to see the fixer give up on them and cancel them.
+
+Fixed prices of price lists are served from the JSON file given with `--price-lists`, an object of
+price list IDs to lists of `prices` nodes. Price lists not in the file have no fixed prices.
+
+    python mock_shopify.py --variants variants.json --port 8080 --bucket 1000 --restore-rate 50
+"""
+
+import argparse
+import json
+import random
+import time
+from http.server import BaseHTTPRequestHandler, HTTPServer
+
+VARIANTS = []
+PRICE_LISTS = {}
+BULK_RESULT = None
+BULK_RUNNING = False
+PORT = 8080
+
+QUERY_COST = 252
+MUTATION_COST = 10
+
+
+class Bucket:
+    def __init__(self, maximum, restore_rate):
+        self.maximum = maximum
+        self.restore_rate = restore_rate
+        self.available = maximum
+        self.updated = time.monotonic()
+
+    def spend(self, cost):
+        now = time.monotonic()
+        self.available = min(self.maximum, self.available + (now - self.updated) * self.restore_rate)
+        self.updated = now
+        if self.available < cost:
+            return False
+        self.available -= cost
+        return True
+
+    def cost(self, cost, throttled):
+        # Like Shopify, the bucket is reported in floats, and a THROTTLED request has no actual
+        # cost because it never ran
+        return {
+            "requestedQueryCost": cost,
+            "actualQueryCost": None if throttled else cost,
+            "throttleStatus": {
+                "maximumAvailable": float(self.maximum),
+                "currentlyAvailable": float(int(self.available)),
+                "restoreRate": float(self.restore_rate),
+            },
+        }
+
+
+BUCKET = Bucket(1000, 50)
+FAIL_RATE = 0.0
+REJECT_SKUS = set()
+
+
+def product_variants_response():
+    return {
+        "data": {
+            "productVariants": {
+                "edges": [{"node": node} for node in VARIANTS],
+                "pageInfo": {
+                    "hasNextPage": False,
+                    "endCursor": "end",
+                    "startCursor": "start",
+                },
+            }
+        }
+    }
+
+
+def nodes_response(variables):
+    by_id = {node["id"]: node for node in VARIANTS}
+    return {"data": {"nodes": [by_id.get(i) for i in variables.get("ids", [])]}}
+
+
+def price_list_response(variables):
+    return {
+        "data": {
+            "priceList": {
+                "prices": {
+                    "nodes": PRICE_LISTS.get(variables.get("id"), []),
+                    "pageInfo": {"hasNextPage": False, "endCursor": None},
+                }
+            }
+        }
+    }
+
+
+def bulk_operation(status):
+    operation = {
+        "id": "gid://shopify/BulkOperation/1",
+        "status": status,
+        "errorCode": None,
+        "objectCount": str(len(VARIANTS)),
+        "url": None,
+    }
+    if status == "COMPLETED":
+        operation["url"] = f"http://localhost:{PORT}/bulk/result.jsonl"
+    return operation
+
+
+def bulk_result():
+    if BULK_RESULT is not None:
+        with open(BULK_RESULT, "rb") as f:
+            return f.read()
+    return "".join(json.dumps(node) + "\n" for node in VARIANTS).encode()
+
+
+def mutation_response(query, variables):
+    if "bulkOperationRunQuery" in query:
+        return {
+            "data": {
+                "bulkOperationRunQuery": {
+                    "bulkOperation": bulk_operation("CREATED"),
+                    "userErrors": [],
+                }
+            }
+        }
+    if "bulkOperationCancel" in query:
+        return {"data": {"bulkOperationCancel": {"userErrors": []}}}
+    if "productVariantsBulkUpdate" in query:
+        rejected = [
+            i
+            for i, v in enumerate(variables.get("variants", []))
+            if v.get("inventoryItem", {}).get("sku", "") in REJECT_SKUS
+        ]
+        if rejected:
+            return {
+                "data": {
+                    "productVariantsBulkUpdate": {
+                        "product": None,
+                        "productVariants": None,
+                        "userErrors": [
+                            {"field": ["variants", str(i), "price"], "message": "Rejected by mock"}
+                            for i in rejected
+                        ],
+                    }
+                }
+            }
+        variants = [
+            {
+                "id": v["id"],
+                "sku": v.get("inventoryItem", {}).get("sku", ""),
+                "price": v.get("price", ""),
+                "compareAtPrice": v.get("compareAtPrice"),
+            }
+            for v in variables.get("variants", [])
+        ]
+        return {
+            "data": {
+                "productVariantsBulkUpdate": {
+                    "product": {"id": variables.get("productId"), "status": "ACTIVE"},
+                    "productVariants": variants,
+                    "userErrors": [],
+                }
+            }
+        }
+    if "priceListFixedPricesUpdate" in query:
+        return {
+            "data": {
+                "priceListFixedPricesUpdate": {
+                    "pricesAdded": [
+                        {"variant": {"id": p["variantId"]}, "price": p["price"]}
+                        for p in variables.get("pricesToAdd", [])
+                    ],
+                    "deletedFixedPriceVariantIds": variables.get("variantIdsToDelete", []),
+                    "userErrors": [],
+                }
+            }
+        }
+    if "inventoryItemUpdate" in query:
+        return {"data": {"inventoryItemUpdate": {"inventoryItem": None, "userErrors": []}}}
+    if "inventorySetQuantities" in query:
+        return {
+            "data": {
+                "inventorySetQuantities": {"inventoryAdjustmentGroup": None, "userErrors": []}
+            }
+        }
+    return {"data": {}}
+
+
+class MockShopifyHandler(BaseHTTPRequestHandler):
+    def do_GET(self):
+        if self.path != "/bulk/result.jsonl":
+            self.send_error(404)
+            return
+
+        payload = bulk_result()
+        self.send_response(200)
+        self.send_header("Content-Type", "application/jsonl")
+        self.send_header("Content-Length", str(len(payload)))
+        self.end_headers()
+        self.wfile.write(payload)
+
+    def do_POST(self):
+        if not self.path.startswith("/admin/api/") or not self.path.endswith("/graphql.json"):
+            self.send_error(404)
+            return
+
+        length = int(self.headers.get("Content-Length", 0))
+        body = json.loads(self.rfile.read(length) or b"{}")
+        query = body.get("query", "")
+        print(json.dumps(body))
+
+        if random.random() < FAIL_RATE:
+            self.send_response(503)
+            self.send_header("Retry-After", "1")
+            self.send_header("Content-Length", "0")
+            self.end_headers()
+            return
+
+        is_mutation = query.lstrip().startswith("mutation")
+        cost = MUTATION_COST if is_mutation else QUERY_COST
+        throttled = not BUCKET.spend(cost)
+        if throttled:
+            response = {
+                "errors": [{"message": "Throttled", "extensions": {"code": "THROTTLED"}}],
+            }
+        elif is_mutation:
+            response = mutation_response(query, body.get("variables", {}))
+        elif "BulkOperation" in query:
+            status = "RUNNING" if BULK_RUNNING else "COMPLETED"
+            response = {"data": {"node": bulk_operation(status)}}
+        elif "nodes(ids:" in query:
+            response = nodes_response(body.get("variables", {}))
+        elif "priceList" in query:
+            response = price_list_response(body.get("variables", {}))
+        elif "productVariants" in query:
+            response = product_variants_response()
+        else:
+            self.send_error(400, "Unsupported query")
+            return
+        response["extensions"] = {"cost": BUCKET.cost(cost, throttled)}
+
+        payload = json.dumps(response).encode()
+        self.send_response(200)
+        self.send_header("Content-Type", "application/json")
+        self.send_header("Content-Length", str(len(payload)))
+        self.end_headers()
+        self.wfile.write(payload)
+
+
+if __name__ == "__main__":
+    parser = argparse.ArgumentParser(description="Serve a mock Shopify admin API")
+    parser.add_argument("--port", type=int, default=8080)
+    parser.add_argument("--variants", default="variants.json")
+    parser.add_argument("--bucket", type=int, default=1000)
+    parser.add_argument("--restore-rate", type=int, default=50)
+    parser.add_argument("--fail-rate", type=float, default=0.0)
+    parser.add_argument("--reject-skus", nargs="*", default=[])
+    parser.add_argument("--bulk-result")
+    parser.add_argument("--bulk-running", action="store_true")
+    parser.add_argument("--price-lists")
+    args = parser.parse_args()
+
+    BUCKET = Bucket(args.bucket, args.restore_rate)
+    FAIL_RATE = args.fail_rate
+    REJECT_SKUS = set(args.reject_skus)
+    BULK_RESULT = args.bulk_result
+    BULK_RUNNING = args.bulk_running
+    PORT = args.port
+
+    with open(args.variants, "r") as f:
+        VARIANTS = json.load(f)
+    if args.price_lists:
+        with open(args.price_lists, "r") as f:
+            PRICE_LISTS = json.load(f)
+
+    print(f"Serving mock Shopify admin API on http://localhost:{args.port}")
+    HTTPServer(("localhost", args.port), MockShopifyHandler).serve_forever()
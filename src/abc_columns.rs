@@ -0,0 +1,699 @@
+use crate::money::{Currency, Money, MoneyError};
+use crate::upc::Upc;
+use serde::{ser::Error, Deserialize, Deserializer};
+
+/// Where each field of the ABC exports is read from, and how it is parsed. The defaults match
+/// the layout of report 7-10. The column names of report 1-15 are assumed, since they have not
+/// been checked against a real "TabOutput.tsv"
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct AbcColumns {
+    /// The columns of "item.data"
+    pub item: ItemColumns,
+
+    /// The columns of "item_posted.data"
+    pub posted: PostedColumns,
+
+    /// The columns of "TabOutput.tsv"
+    pub tab_output: TabOutputColumns,
+
+    /// The character encoding of every ABC file
+    pub encoding: AbcEncoding,
+}
+
+/// The character encoding ABC wrote its exports in
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum AbcEncoding {
+    /// UTF-8 if the file starts with a UTF-8 byte order mark or is valid UTF-8, otherwise
+    /// Windows-1252, the code page ABC writes in on Windows. `"auto"`
+    #[default]
+    Auto,
+
+    /// One encoding given by its label, like `"windows-1252"`, `"latin1"` or `"utf-8"`
+    Label(&'static encoding_rs::Encoding),
+}
+
+impl AbcEncoding {
+    /// Read a whole ABC file and transcode it to UTF-8
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The path to the file
+    ///
+    /// # Returns
+    ///
+    /// The contents of the file as UTF-8, without a byte order mark. Files in UTF-8 are kept as
+    /// they are, so a row that is not valid UTF-8 is still reported by the CSV reader
+    ///
+    /// # Errors
+    ///
+    /// Will return `csv::Error` if the file cannot be read
+    pub fn read(&self, path: &str) -> Result<Vec<u8>, csv::Error> {
+        let bytes = std::fs::read(path)?;
+        let encoding = match self {
+            AbcEncoding::Auto => match encoding_rs::Encoding::for_bom(&bytes) {
+                Some((e, _)) => e,
+                None if std::str::from_utf8(&bytes).is_ok() => encoding_rs::UTF_8,
+                None => encoding_rs::WINDOWS_1252,
+            },
+            AbcEncoding::Label(e) => e,
+        };
+        if encoding == encoding_rs::UTF_8 {
+            return Ok(match bytes.strip_prefix(b"\xEF\xBB\xBF") {
+                Some(b) => b.to_vec(),
+                None => bytes,
+            });
+        }
+        let (decoded, _, _) = encoding.decode(&bytes);
+        Ok(decoded.into_owned().into_bytes())
+    }
+}
+
+impl<'de> Deserialize<'de> for AbcEncoding {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let label = String::deserialize(deserializer)?;
+        if label.trim().eq_ignore_ascii_case("auto") {
+            return Ok(AbcEncoding::Auto);
+        }
+        encoding_rs::Encoding::for_label(label.as_bytes())
+            .map(AbcEncoding::Label)
+            .ok_or_else(|| serde::de::Error::custom(format!("{:?} is not a known encoding", label)))
+    }
+}
+
+/// The columns of "item.data"
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct ItemColumns {
+    /// Whether the first row holds column names instead of an item
+    pub has_headers: bool,
+
+    /// The number of columns every row must have. If left blank, every row must have as many as
+    /// the first
+    pub column_count: Option<usize>,
+
+    pub sku: Column<TextParser>,
+    pub desc: Column<TextParser>,
+    pub list: Column<PriceParser>,
+    pub cost: Column<PriceParser>,
+    pub upcs: Column<UpcParser>,
+
+    /// The column holding a promotional price. If left blank, promo prices are not read
+    pub promo: Option<Column<PriceParser>>,
+}
+
+impl Default for ItemColumns {
+    fn default() -> Self {
+        ItemColumns {
+            has_headers: false,
+            column_count: None,
+            sku: Column::Plain(ColumnRef::Index(0)),
+            desc: Column::Plain(ColumnRef::Index(1)),
+            list: Column::Plain(ColumnRef::Index(6)),
+            cost: Column::Plain(ColumnRef::Index(8)),
+            upcs: Column::Plain(ColumnRef::Index(43)),
+            promo: None,
+        }
+    }
+}
+
+/// The columns of "item_posted.data"
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct PostedColumns {
+    /// Whether the first row holds column names instead of an item
+    pub has_headers: bool,
+
+    /// The number of columns every row must have. If left blank, every row must have as many as
+    /// the first
+    pub column_count: Option<usize>,
+
+    pub sku: Column<TextParser>,
+    pub stock: Column<StockParser>,
+}
+
+impl Default for PostedColumns {
+    fn default() -> Self {
+        PostedColumns {
+            has_headers: false,
+            column_count: None,
+            sku: Column::Plain(ColumnRef::Index(0)),
+            stock: Column::Plain(ColumnRef::Index(19)),
+        }
+    }
+}
+
+/// The columns of "TabOutput.tsv", which holds both the prices and the stock of each item
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct TabOutputColumns {
+    /// Whether the first row holds column names instead of an item
+    pub has_headers: bool,
+
+    /// The number of columns every row must have. If left blank, every row must have as many as
+    /// the first
+    pub column_count: Option<usize>,
+
+    pub sku: Column<TextParser>,
+    pub desc: Column<TextParser>,
+    pub list: Column<PriceParser>,
+    pub stock: Column<StockParser>,
+
+    /// The column holding the cost. If left blank, costs are not read or synced
+    pub cost: Option<Column<PriceParser>>,
+
+    /// The column holding UPCs. If left blank, items are only matched by SKU
+    pub upcs: Option<Column<UpcParser>>,
+
+    /// The column holding a promotional price. If left blank, promo prices are not read
+    pub promo: Option<Column<PriceParser>>,
+}
+
+/// A column read by its name in the header row
+fn header<P>(name: &str) -> Column<P> {
+    Column::Plain(ColumnRef::Header(name.to_string()))
+}
+
+impl Default for TabOutputColumns {
+    fn default() -> Self {
+        TabOutputColumns {
+            has_headers: true,
+            column_count: None,
+            sku: header("Sku"),
+            desc: header("Description"),
+            list: header("List"),
+            stock: header("Stock"),
+            cost: None,
+            upcs: None,
+            promo: None,
+        }
+    }
+}
+
+/// A column given by its index, counting from 0, or by its name in the header row
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(untagged)]
+pub enum ColumnRef {
+    Index(usize),
+    Header(String),
+}
+
+impl std::fmt::Display for ColumnRef {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ColumnRef::Index(i) => write!(f, "column {}", i),
+            ColumnRef::Header(h) => write!(f, "column {:?}", h),
+        }
+    }
+}
+
+/// Where one field is read from. Either just the column, like `6` or `"List Price"`, or the
+/// column and how to parse it, like `{"column": 6, "parser": "cents"}`
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+pub enum Column<P> {
+    Plain(ColumnRef),
+    WithParser {
+        column: ColumnRef,
+        #[serde(default)]
+        parser: P,
+    },
+}
+
+impl<P: Copy + Default> Column<P> {
+    pub fn column(&self) -> &ColumnRef {
+        match self {
+            Column::Plain(c) => c,
+            Column::WithParser { column, .. } => column,
+        }
+    }
+
+    pub fn parser(&self) -> P {
+        match self {
+            Column::Plain(_) => P::default(),
+            Column::WithParser { parser, .. } => *parser,
+        }
+    }
+}
+
+/// How a text field is read
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TextParser {
+    /// Keep the field exactly as written
+    #[default]
+    Raw,
+
+    /// Remove leading and trailing whitespace
+    Trim,
+}
+
+impl TextParser {
+    pub fn parse(&self, raw: &str) -> String {
+        match self {
+            TextParser::Raw => raw.to_string(),
+            TextParser::Trim => raw.trim().to_string(),
+        }
+    }
+}
+
+/// How a price field is read
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PriceParser {
+    /// A decimal amount, like "12.99". See `Money::parse`
+    #[default]
+    Decimal,
+
+    /// A whole number of cents, like "1299"
+    Cents,
+}
+
+impl PriceParser {
+    /// # Errors
+    ///
+    /// Returns a `MoneyError` if `raw` is not a price in this format
+    pub fn parse(&self, raw: &str, currency: Currency) -> Result<Money, MoneyError> {
+        match self {
+            PriceParser::Decimal => Money::parse(raw, currency),
+            PriceParser::Cents => raw
+                .trim()
+                .parse::<i64>()
+                .map(|c| Money::new(c, currency))
+                .map_err(|_| MoneyError::Invalid(raw.to_string())),
+        }
+    }
+
+    /// Parse a price like `PriceParser::parse`, but round fractions of a cent instead of
+    /// rejecting them. See `Money::parse_rounded`
+    ///
+    /// # Errors
+    ///
+    /// Returns a `MoneyError` if `raw` is not a price in this format
+    pub fn parse_rounded(&self, raw: &str, currency: Currency) -> Result<Money, MoneyError> {
+        match self {
+            PriceParser::Decimal => Money::parse_rounded(raw, currency),
+            PriceParser::Cents => self.parse(raw, currency),
+        }
+    }
+}
+
+/// How a stock field is read
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StockParser {
+    /// A number with an optional leading sign, like "12" or "-1.5"
+    #[default]
+    Decimal,
+
+    /// A number with an optional trailing sign, like "12" or "1.5-"
+    TrailingSign,
+}
+
+impl StockParser {
+    /// # Errors
+    ///
+    /// Returns the raw field if it is not a number in this format
+    pub fn parse(&self, raw: &str) -> Result<f64, String> {
+        let trimmed = raw.trim();
+        let stock = match self {
+            StockParser::Decimal => trimmed.parse::<f64>(),
+            StockParser::TrailingSign => match trimmed.strip_suffix('-') {
+                Some(s) => s.trim_end().parse::<f64>().map(|s| -s),
+                None => trimmed.parse::<f64>(),
+            },
+        };
+        stock.map_err(|_| raw.to_string())
+    }
+}
+
+/// How a UPC field is read. Invalid UPCs are skipped either way
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum UpcParser {
+    /// ABC's list of UPCs. See `Upc::from_abc_upc_list`
+    #[default]
+    List,
+
+    /// A single UPC
+    Single,
+}
+
+impl UpcParser {
+    pub fn parse(&self, raw: &str) -> Vec<Upc> {
+        match self {
+            UpcParser::List => Upc::from_abc_upc_list(raw).into_iter().flatten().collect(),
+            UpcParser::Single => Upc::try_from(raw.trim()).into_iter().collect(),
+        }
+    }
+}
+
+/// The shape of one ABC file, taken from its first row, that every other row is checked against
+pub struct Schema {
+    /// The name of the file for error messages. Like "item.data"
+    name: String,
+
+    /// The column names, if the file has a header row
+    headers: Option<csv::StringRecord>,
+
+    /// The number of columns every row must have
+    len: usize,
+}
+
+impl Schema {
+    /// Read the first row of an ABC file to learn its shape, without consuming it unless it is a
+    /// header row
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - The name of the file for error messages
+    /// * `reader` - The reader of the file, built with `has_headers` matching the file
+    /// * `has_headers` - Whether the first row holds column names
+    /// * `column_count` - The number of columns every row must have, if known
+    ///
+    /// # Errors
+    ///
+    /// Will return `csv::Error` if the file cannot be read, or its first row does not have
+    /// `column_count` columns
+    pub fn read<R: std::io::Read>(
+        name: &str,
+        reader: &mut csv::Reader<R>,
+        has_headers: bool,
+        column_count: Option<usize>,
+    ) -> Result<Self, csv::Error> {
+        let first = reader.headers()?.clone();
+        let len = column_count.unwrap_or(first.len());
+        if first.len() != len {
+            return Err(csv::Error::custom(format!(
+                "The first row of {} has {} columns, but {} are expected. ABC may have changed \
+                 the layout of its export, so check abc_columns in config.json",
+                name,
+                first.len(),
+                len
+            )));
+        }
+        Ok(Schema {
+            name: name.to_string(),
+            headers: if has_headers { Some(first) } else { None },
+            len,
+        })
+    }
+
+    /// Find the index of the column a field is read from
+    ///
+    /// # Arguments
+    ///
+    /// * `field` - The name of the field for error messages
+    /// * `column` - The column of the field
+    ///
+    /// # Errors
+    ///
+    /// Will return `csv::Error` if the column is not in the file
+    pub fn index(&self, field: &str, column: &ColumnRef) -> Result<usize, csv::Error> {
+        let index = match (column, &self.headers) {
+            (ColumnRef::Index(i), _) => *i,
+            (ColumnRef::Header(h), Some(headers)) => headers
+                .iter()
+                .position(|c| c.trim().eq_ignore_ascii_case(h.trim()))
+                .ok_or(csv::Error::custom(format!(
+                    "{} is read from {}, but {} has no such column. Its columns are {:?}",
+                    field,
+                    column,
+                    &self.name,
+                    headers.iter().collect::<Vec<_>>()
+                )))?,
+            (ColumnRef::Header(_), None) => {
+                return Err(csv::Error::custom(format!(
+                    "{} is read from {}, but {} has no header row",
+                    field, column, &self.name
+                )))
+            }
+        };
+        if index >= self.len {
+            return Err(csv::Error::custom(format!(
+                "{} is read from {}, but {} only has {} columns. ABC may have changed the layout \
+                 of its export, so check abc_columns in config.json",
+                field, column, &self.name, self.len
+            )));
+        }
+        Ok(index)
+    }
+
+    /// Check that a row has as many columns as the schema
+    ///
+    /// # Arguments
+    ///
+    /// * `row` - The row to check
+    /// * `row_number` - The number of the row for error messages
+    ///
+    /// # Errors
+    ///
+    /// Will return a `Reject` of the whole row if it has more or fewer columns
+    pub fn check(&self, row: &csv::StringRecord, row_number: usize) -> Result<(), Reject> {
+        if row.len() != self.len {
+            return Err(self.reject(
+                row_number,
+                "row",
+                &row.iter().collect::<Vec<_>>().join("\t"),
+                format!("has {} columns, but {} are expected", row.len(), self.len),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Describe a field of this file that cannot be parsed
+    ///
+    /// # Arguments
+    ///
+    /// * `row_number` - The number of the row
+    /// * `field` - The name of the field, or "row" if the whole row is bad
+    /// * `raw` - The field as written in the file
+    /// * `reason` - Why it cannot be parsed
+    pub fn reject<R: std::fmt::Display>(
+        &self,
+        row_number: usize,
+        field: &str,
+        raw: &str,
+        reason: R,
+    ) -> Reject {
+        Reject {
+            file: self.name.to_owned(),
+            row: row_number,
+            field: field.to_string(),
+            raw: raw.to_string(),
+            reason: reason.to_string(),
+        }
+    }
+}
+
+/// A row of an ABC file that was skipped because a field in it cannot be parsed
+#[derive(Debug, Clone)]
+pub struct Reject {
+    /// The name of the file. Like "item.data"
+    pub file: String,
+
+    /// The number of the row, counting from 1 and not counting the header row
+    pub row: usize,
+
+    /// The field that cannot be parsed, like "list", or "row" if the whole row is bad
+    pub field: String,
+
+    /// The field as written in the file
+    pub raw: String,
+
+    /// Why the field cannot be parsed
+    pub reason: String,
+}
+
+impl std::fmt::Display for Reject {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Cannot parse {} {:?} in row {} of {}: {}",
+            &self.field, &self.raw, self.row, &self.file, &self.reason
+        )
+    }
+}
+
+/// What to do with rows of an ABC file that cannot be parsed. Either fail on the first one, or
+/// skip each of them and keep it for the rejects report
+#[derive(Debug, Clone, Default)]
+pub struct Rejects {
+    /// Whether bad rows are skipped instead of failing the parse
+    lenient: bool,
+
+    /// The rows skipped so far
+    rows: Vec<Reject>,
+}
+
+impl Rejects {
+    /// Fail the parse on the first row that cannot be parsed
+    pub fn strict() -> Self {
+        Rejects::default()
+    }
+
+    /// Skip every row that cannot be parsed and keep it for the rejects report
+    pub fn lenient() -> Self {
+        Rejects {
+            lenient: true,
+            rows: Vec::new(),
+        }
+    }
+
+    /// The rows skipped so far
+    pub fn rows(&self) -> &[Reject] {
+        &self.rows
+    }
+
+    /// Skip a bad row if lenient, or fail if strict
+    ///
+    /// # Errors
+    ///
+    /// Will return `csv::Error` describing `reject` if strict
+    pub fn add(&mut self, reject: Reject) -> Result<(), csv::Error> {
+        if !self.lenient {
+            return Err(csv::Error::custom(reject.to_string()));
+        }
+        self.rows.push(reject);
+        Ok(())
+    }
+}
+
+/// What to do with a row of "item_posted.data" whose SKU is not in "item.data", which happens
+/// after an item is deleted in ABC
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OrphanPolicy {
+    /// Skip the row without a word. `"ignore"`
+    Ignore,
+
+    /// Skip the row and list it in the warnings. `"warn"`
+    #[default]
+    Warn,
+
+    /// Fail the parse. `"error"`
+    Error,
+}
+
+/// Items that are only in one of "item.data" and "item_posted.data"
+#[derive(Debug, Clone, Default)]
+pub struct Unmatched {
+    /// The SKU and row number of every row of "item_posted.data" skipped as an orphan. Empty if
+    /// orphans are ignored
+    pub orphans: Vec<(String, usize)>,
+
+    /// The SKU of every item in "item.data" with no row in "item_posted.data", whose stock is
+    /// taken as 0
+    pub unposted: Vec<String>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Write bytes to a temporary path
+    fn write_file(name: &str, bytes: &[u8]) -> String {
+        let path = std::env::temp_dir().join(format!(
+            "shopify-price-fixer-{}-{}",
+            std::process::id(),
+            name
+        ));
+        std::fs::write(&path, bytes).unwrap();
+        path.to_string_lossy().into_owned()
+    }
+
+    fn reader(text: &str, has_headers: bool) -> csv::Reader<&[u8]> {
+        csv::ReaderBuilder::new()
+            .delimiter(b'\t')
+            .has_headers(has_headers)
+            .flexible(true)
+            .from_reader(text.as_bytes())
+    }
+
+    #[test]
+    fn schema_finds_columns_by_header_ignoring_case() {
+        let mut r = reader("Sku\tDescription\t List \nA1\tItem\t12.99\n", true);
+        let schema = Schema::read("TabOutput.tsv", &mut r, true, None).unwrap();
+        let list = ColumnRef::Header("list".to_string());
+        assert_eq!(schema.index("list", &list).unwrap(), 2);
+        let stock = ColumnRef::Header("Stock".to_string());
+        assert!(schema.index("stock", &stock).is_err());
+    }
+
+    #[test]
+    fn schema_rejects_columns_past_the_end() {
+        let mut r = reader("A1\tItem\t12.99\n", false);
+        let schema = Schema::read("item.data", &mut r, false, None).unwrap();
+        assert_eq!(schema.index("list", &ColumnRef::Index(2)).unwrap(), 2);
+        assert!(schema.index("cost", &ColumnRef::Index(3)).is_err());
+        let header = ColumnRef::Header("Sku".to_string());
+        assert!(schema.index("sku", &header).is_err());
+    }
+
+    #[test]
+    fn schema_fails_on_a_first_row_of_the_wrong_length() {
+        let mut r = reader("A1\tItem\t12.99\n", false);
+        assert!(Schema::read("item.data", &mut r, false, Some(4)).is_err());
+    }
+
+    #[test]
+    fn schema_rejects_rows_of_another_length() {
+        let mut r = reader("A1\tItem\t12.99\nB2\tItem\n", false);
+        let schema = Schema::read("item.data", &mut r, false, None).unwrap();
+        let rows: Vec<_> = r.records().map(Result::unwrap).collect();
+        assert!(schema.check(&rows[0], 1).is_ok());
+        let reject = schema.check(&rows[1], 2).unwrap_err();
+        assert_eq!(reject.row, 2);
+        assert_eq!(reject.field, "row");
+        assert_eq!(reject.raw, "B2\tItem");
+    }
+
+    #[test]
+    fn strict_rejects_fail_and_lenient_rejects_are_kept() {
+        let mut r = reader("A1\tItem\n", false);
+        let schema = Schema::read("item.data", &mut r, false, None).unwrap();
+        let reject = || schema.reject(3, "list", "1.2.3", "Not a price");
+
+        assert!(Rejects::strict().add(reject()).is_err());
+
+        let mut rejects = Rejects::lenient();
+        rejects.add(reject()).unwrap();
+        assert_eq!(rejects.rows().len(), 1);
+        assert_eq!(
+            rejects.rows()[0].to_string(),
+            "Cannot parse list \"1.2.3\" in row 3 of item.data: Not a price"
+        );
+    }
+
+    #[test]
+    fn auto_encoding_reads_windows_1252() {
+        let path = write_file("cp1252.data", b"A1\tCaf\xe9 \x93Deluxe\x94\n");
+        let bytes = AbcEncoding::Auto.read(&path).unwrap();
+        assert_eq!(
+            String::from_utf8(bytes).unwrap(),
+            "A1\tCaf\u{e9} \u{201c}Deluxe\u{201d}\n"
+        );
+    }
+
+    #[test]
+    fn auto_encoding_keeps_utf8_and_strips_its_bom() {
+        let text = "A1\tCaf\u{e9}\n";
+        let path = write_file("utf8.data", text.as_bytes());
+        assert_eq!(AbcEncoding::Auto.read(&path).unwrap(), text.as_bytes());
+
+        let path = write_file(
+            "utf8-bom.data",
+            &[b"\xEF\xBB\xBF", text.as_bytes()].concat(),
+        );
+        assert_eq!(AbcEncoding::Auto.read(&path).unwrap(), text.as_bytes());
+    }
+
+    #[test]
+    fn labelled_encoding_is_used_even_for_valid_utf8() {
+        let encoding: AbcEncoding = serde_json::from_str("\"latin1\"").unwrap();
+        let path = write_file("latin1.data", "Caf\u{e9}".as_bytes());
+        let bytes = encoding.read(&path).unwrap();
+        assert_eq!(String::from_utf8(bytes).unwrap(), "Caf\u{c3}\u{a9}");
+        assert!(serde_json::from_str::<AbcEncoding>("\"klingon\"").is_err());
+    }
+}
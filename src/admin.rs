@@ -0,0 +1,448 @@
+use crate::product::Cost;
+use crate::retry::RetryPolicy;
+use crate::throttle::Throttle;
+use crate::{Config, FixerError};
+use chrono::{Months, NaiveDate};
+use reqwest::header::{HeaderMap, RETRY_AFTER};
+use serde::{de::DeserializeOwned, Deserialize};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Shopify supports each stable API version for at least 12 months after its release
+const SUPPORT_MONTHS: u32 = 12;
+
+/// Warn when the configured API version is this many days or fewer from the end of its support
+const DEPRECATION_WARNING_DAYS: i64 = 90;
+
+/// A stable version of the Shopify admin API. Like "2024-10"
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct ApiVersion {
+    year: i32,
+    month: u32,
+}
+
+/// Whether Shopify still serves a given `ApiVersion`
+#[derive(Debug, PartialEq, Eq)]
+pub enum VersionSupport {
+    /// The version is supported and not close to deprecation
+    Supported,
+
+    /// The version is supported, but Shopify stops supporting it on the contained date
+    NearDeprecation(NaiveDate),
+
+    /// Shopify stopped supporting the version on the contained date
+    Unsupported(NaiveDate),
+
+    /// The version will not be released until the contained date
+    Unreleased(NaiveDate),
+}
+
+impl ApiVersion {
+    /// The first day this version was available
+    pub fn release_date(&self) -> NaiveDate {
+        NaiveDate::from_ymd_opt(self.year, self.month, 1)
+            .expect("ApiVersion always holds a valid year and quarter month")
+    }
+
+    /// The first day Shopify no longer guarantees support for this version
+    pub fn end_of_support(&self) -> NaiveDate {
+        self.release_date() + Months::new(SUPPORT_MONTHS)
+    }
+
+    /// Check whether this version is supported on a given day
+    ///
+    /// # Arguments
+    ///
+    /// * `today` - The day to check support for
+    ///
+    /// # Returns
+    ///
+    /// The `VersionSupport` of this version on `today`
+    pub fn support(&self, today: NaiveDate) -> VersionSupport {
+        let end = self.end_of_support();
+        if today < self.release_date() {
+            VersionSupport::Unreleased(self.release_date())
+        } else if today >= end {
+            VersionSupport::Unsupported(end)
+        } else if (end - today).num_days() <= DEPRECATION_WARNING_DAYS {
+            VersionSupport::NearDeprecation(end)
+        } else {
+            VersionSupport::Supported
+        }
+    }
+}
+
+impl std::fmt::Display for ApiVersion {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:04}-{:02}", self.year, self.month)
+    }
+}
+
+impl std::str::FromStr for ApiVersion {
+    type Err = FixerError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || {
+            FixerError::Custom(format!(
+                "Invalid api_version {}. Must be a stable version like \"2024-10\"",
+                s
+            ))
+        };
+        let (year, month) = s.trim().split_once('-').ok_or_else(invalid)?;
+        let year: i32 = year.parse().or(Err(invalid()))?;
+        let month: u32 = month.parse().or(Err(invalid()))?;
+
+        // Shopify releases a stable version at the start of every quarter
+        if ![1, 4, 7, 10].contains(&month) || NaiveDate::from_ymd_opt(year, month, 1).is_none() {
+            return Err(invalid());
+        }
+        Ok(ApiVersion { year, month })
+    }
+}
+
+/// The envelope Shopify wraps around every GraphQL response. The data and extensions are kept as
+/// JSON and parsed only after the errors are checked, so a THROTTLED or other error response is
+/// never mistaken for a response that does not parse
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct GraphqlResponse {
+    pub data: Option<serde_json::Value>,
+    pub errors: Option<Vec<GraphqlError>>,
+    pub extensions: Option<serde_json::Value>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct GraphqlError {
+    pub message: String,
+    pub extensions: Option<GraphqlErrorExtensions>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct GraphqlErrorExtensions {
+    pub code: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct Extensions {
+    pub cost: Option<Cost>,
+}
+
+/// Sends every query and mutation to the Shopify admin GraphQL API for a single `ApiVersion`
+#[derive(Debug, Clone)]
+pub struct AdminClient {
+    client: reqwest::Client,
+    headers: HeaderMap,
+    graphql_url: String,
+    api_version: ApiVersion,
+    throttle: Arc<Throttle>,
+    retry: RetryPolicy,
+}
+
+impl AdminClient {
+    /// Build a client for the admin API described by `config`
+    ///
+    /// # Arguments
+    ///
+    /// * `config` - The `Config` holding the access token, API version, and admin URL
+    ///
+    /// # Returns
+    ///
+    /// The `AdminClient` if successful
+    ///
+    /// # Errors
+    ///
+    /// Will return `FixerError::Custom` if `api_version` is not a valid stable version or if the
+    /// access token cannot be used as a header value
+    pub fn new(config: &Config) -> Result<Self, FixerError> {
+        let api_version: ApiVersion = config.api_version.parse()?;
+
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            "Content-Type",
+            "application/json"
+                .parse()
+                .expect("application/json is a valid header value"),
+        );
+        headers.insert(
+            "X-Shopify-Access-Token",
+            config
+                .shopify_access_token
+                .parse()
+                .or(Err(FixerError::Custom(
+                    "Encountered InvalidHeaderValue when reading shopify_access_token".to_string(),
+                )))?,
+        );
+
+        Ok(AdminClient {
+            client: reqwest::Client::new(),
+            headers,
+            graphql_url: config.admin_url(&format!("{}/graphql.json", api_version)),
+            api_version,
+            throttle: Arc::new(Throttle::default()),
+            retry: config.retry.clone(),
+        })
+    }
+
+    /// The version of the admin API this client talks to
+    pub fn api_version(&self) -> ApiVersion {
+        self.api_version
+    }
+
+    /// Check whether Shopify supports the API version of this client today
+    pub fn version_support(&self) -> VersionSupport {
+        let today = chrono::Utc::now().date_naive();
+        self.api_version.support(today)
+    }
+
+    /// Send a GraphQL query or mutation and parse the response. Waits first if Shopify would
+    /// throttle the request, records the cost Shopify reports for it, and retries transient
+    /// failures according to the `RetryPolicy` from the config
+    ///
+    /// # Arguments
+    ///
+    /// * `body` - The JSON request body, holding the "query" and optionally its "variables"
+    ///
+    /// # Returns
+    ///
+    /// The "data" of the response parsed into `T`
+    ///
+    /// # Errors
+    ///
+    /// * Will return `FixerError::RetriesExhausted` if every attempt failed with a transient error
+    /// * Will return `FixerError::Http` if the response has a status that should not be retried
+    /// * Will return `FixerError::SerdeJson` if the response does not match `T`
+    /// * Will return `FixerError::Graphql` if the response holds errors
+    pub async fn graphql<T>(&self, body: &serde_json::Value) -> Result<T, FixerError>
+    where
+        T: DeserializeOwned,
+    {
+        self.retry.run(|| self.send_graphql(body)).await
+    }
+
+    /// Send a GraphQL request once. See `graphql`
+    async fn send_graphql<T>(&self, body: &serde_json::Value) -> Result<T, FixerError>
+    where
+        T: DeserializeOwned,
+    {
+        let query = body["query"].as_str().unwrap_or_default();
+        let reserved = self.throttle.acquire(query).await;
+
+        // Shopify only reports the cost of a request it answered, so give back the points
+        // reserved for one that failed before that
+        let response = match self.post_graphql(body).await {
+            Ok(text) => serde_json::from_str::<GraphqlResponse>(&text).map_err(FixerError::from),
+            Err(e) => Err(e),
+        };
+        match response {
+            Ok(r) => read_graphql_response(&self.throttle, query, r),
+            Err(e) => {
+                self.throttle.release(reserved);
+                Err(e)
+            }
+        }
+    }
+
+    /// Post a GraphQL request body and read the response body
+    ///
+    /// # Errors
+    ///
+    /// * Will return `FixerError::Http` if the response does not have a success status
+    /// * Will return `FixerError::Reqwest` if the request cannot be sent or the body cannot be read
+    async fn post_graphql(&self, body: &serde_json::Value) -> Result<String, FixerError> {
+        let response = self
+            .client
+            .post(&self.graphql_url)
+            .headers(self.headers.to_owned())
+            .body(body.to_string())
+            .send()
+            .await?;
+        let status = response.status();
+        if !status.is_success() {
+            let retry_after = response
+                .headers()
+                .get(RETRY_AFTER)
+                .and_then(|r| r.to_str().ok())
+                .and_then(parse_retry_after);
+            return Err(FixerError::Http {
+                status: status.as_u16(),
+                retry_after,
+            });
+        }
+        Ok(response.text().await?)
+    }
+
+    /// Download a file Shopify links to, like the result of a bulk operation. The URL is already
+    /// signed, so the access token is not sent with it
+    ///
+    /// # Arguments
+    ///
+    /// * `url` - The URL of the file
+    ///
+    /// # Returns
+    ///
+    /// The body of the file
+    ///
+    /// # Errors
+    ///
+    /// Will return a `FixerError` if the download still fails after retrying
+    pub async fn download(&self, url: &str) -> Result<String, FixerError> {
+        self.retry
+            .run(|| async {
+                let response = self.client.get(url).send().await?;
+                let status = response.status();
+                if !status.is_success() {
+                    return Err(FixerError::Http {
+                        status: status.as_u16(),
+                        retry_after: None,
+                    });
+                }
+                Ok(response.text().await?)
+            })
+            .await
+    }
+}
+
+/// Read the seconds to wait from a Retry-After header
+///
+/// # Arguments
+///
+/// * `retry_after` - The value of the header. Like "2" or "0.5"
+///
+/// # Returns
+///
+/// How long to wait, or `None` if the value is not a number of seconds that fits in a `Duration`,
+/// like "-1", "inf" or "NaN". An HTTP date is not supported and is `None` too
+fn parse_retry_after(retry_after: &str) -> Option<Duration> {
+    let seconds = retry_after.trim().parse::<f64>().ok()?;
+    Duration::try_from_secs_f64(seconds).ok()
+}
+
+/// Record the cost of a GraphQL response, check it for errors, and parse its data
+///
+/// # Arguments
+///
+/// * `throttle` - The `Throttle` to record the cost in
+/// * `query` - The GraphQL document that was sent
+/// * `response` - The response to read
+///
+/// # Returns
+///
+/// The "data" of the response parsed into `T`
+///
+/// # Errors
+///
+/// * Will return `FixerError::Throttled` if Shopify rejected the request as THROTTLED
+/// * Will return `FixerError::Graphql` if the response holds other errors
+/// * Will return `FixerError::SerdeJson` if the data does not match `T`
+pub fn read_graphql_response<T>(
+    throttle: &Throttle,
+    query: &str,
+    response: GraphqlResponse,
+) -> Result<T, FixerError>
+where
+    T: DeserializeOwned,
+{
+    // The cost only drives the throttle, so a cost that does not parse is skipped instead of
+    // failing a response that is otherwise fine
+    let extensions = response
+        .extensions
+        .and_then(|e| serde_json::from_value::<Extensions>(e).ok());
+    if let Some(cost) = extensions.and_then(|e| e.cost) {
+        throttle.update(query, cost.requested_query_cost, cost.throttle_status);
+    }
+    if let Some(errors) = response.errors.filter(|e| !e.is_empty()) {
+        let throttled = errors.iter().any(|e| {
+            e.extensions
+                .as_ref()
+                .and_then(|x| x.code.as_deref())
+                .is_some_and(|code| code == "THROTTLED")
+        });
+        if throttled {
+            return Err(FixerError::Throttled);
+        }
+        return Err(FixerError::Graphql(errors));
+    }
+    let data = response.data.ok_or(FixerError::Custom(
+        "GraphQL response has no data".to_string(),
+    ))?;
+    Ok(serde_json::from_value(data)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read(text: &str) -> Result<serde_json::Value, FixerError> {
+        let response: GraphqlResponse = serde_json::from_str(text).unwrap();
+        read_graphql_response(&Throttle::default(), "query", response)
+    }
+
+    #[test]
+    fn throttled_is_detected_even_if_the_cost_does_not_parse() {
+        let e = read(
+            r#"{
+                "errors": [{"message": "Throttled", "extensions": {"code": "THROTTLED"}}],
+                "extensions": {"cost": {"requestedQueryCost": "unexpected"}}
+            }"#,
+        )
+        .unwrap_err();
+        assert!(matches!(e, FixerError::Throttled));
+        assert!(e.is_transient());
+    }
+
+    #[test]
+    fn throttled_is_detected_with_shopify_float_cost() {
+        let e = read(
+            r#"{
+                "errors": [{"message": "Throttled", "extensions": {"code": "THROTTLED"}}],
+                "extensions": {"cost": {
+                    "requestedQueryCost": 752,
+                    "actualQueryCost": null,
+                    "throttleStatus": {
+                        "maximumAvailable": 2000.0,
+                        "currentlyAvailable": 10.0,
+                        "restoreRate": 100.0
+                    }
+                }}
+            }"#,
+        )
+        .unwrap_err();
+        assert!(matches!(e, FixerError::Throttled));
+    }
+
+    #[test]
+    fn other_errors_are_not_transient() {
+        let e = read(r#"{"errors": [{"message": "Field 'x' doesn't exist"}]}"#).unwrap_err();
+        assert!(matches!(e, FixerError::Graphql(_)));
+        assert!(!e.is_transient());
+    }
+
+    #[test]
+    fn retry_after_is_read_in_seconds() {
+        assert_eq!(parse_retry_after("2"), Some(Duration::from_secs(2)));
+        assert_eq!(parse_retry_after(" 0.5 "), Some(Duration::from_millis(500)));
+    }
+
+    #[test]
+    fn bad_retry_after_is_ignored() {
+        for value in [
+            "-1",
+            "inf",
+            "NaN",
+            "1e30",
+            "soon",
+            "Wed, 21 Oct 2015 07:28:00 GMT",
+        ] {
+            assert_eq!(parse_retry_after(value), None, "{}", value);
+        }
+    }
+
+    #[test]
+    fn data_is_parsed() {
+        let data = read(r#"{"data": {"shop": {"name": "Store"}}}"#).unwrap();
+        assert_eq!(data["shop"]["name"], "Store");
+    }
+}
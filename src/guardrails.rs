@@ -0,0 +1,414 @@
+use crate::plan::{Change, Plan};
+use serde::Deserialize;
+use std::collections::HashSet;
+
+/// The default limit on the share of matched variants a run may take to zero stock
+pub const DEFAULT_MAX_ZEROED_STOCK_PERCENT: f64 = 10.0;
+
+/// The default limit on the average price change of a run
+pub const DEFAULT_MAX_AVERAGE_PRICE_CHANGE_PERCENT: f64 = 50.0;
+
+/// Limits on how much one run may change, checked before anything is sent to Shopify. A run that
+/// exceeds any of them is aborted, since it most likely comes from a broken ABC export. A limit
+/// set to `null` is off
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct Guardrails {
+    /// The most variants a run may change
+    pub max_variants_changed: Option<usize>,
+
+    /// The largest average price change, up or down, in percent
+    pub max_average_price_change_percent: Option<f64>,
+
+    /// The largest change to any single price, up or down, in percent
+    pub max_price_change_percent: Option<f64>,
+
+    /// The most variants a run may take from some stock to zero stock at any location
+    pub max_zeroed_stock: Option<usize>,
+
+    /// The largest share of the matched variants a run may take from some stock to zero stock at
+    /// any location, in percent. Not checked for plans that do not record how many variants
+    /// were matched
+    pub max_zeroed_stock_percent: Option<f64>,
+}
+
+impl Default for Guardrails {
+    fn default() -> Self {
+        Guardrails {
+            max_variants_changed: None,
+            max_average_price_change_percent: Some(DEFAULT_MAX_AVERAGE_PRICE_CHANGE_PERCENT),
+            max_price_change_percent: None,
+            max_zeroed_stock: None,
+            max_zeroed_stock_percent: Some(DEFAULT_MAX_ZEROED_STOCK_PERCENT),
+        }
+    }
+}
+
+/// How much a `Plan` changes
+#[derive(Debug, Clone, Default)]
+pub struct PlanTotals {
+    /// The number of variants with at least one change
+    pub variants_changed: usize,
+
+    /// The number of price changes that change the price, including market prices that change
+    /// from one fixed price to another. A change that only replaces the SKU is not counted
+    pub price_changes: usize,
+
+    /// The average price change, up or down, in percent
+    pub average_price_change_percent: f64,
+
+    /// The largest price change, up or down, in percent
+    pub max_price_change_percent: f64,
+
+    /// The number of variants that go from some stock to zero stock at any location
+    pub zeroed_stock: usize,
+
+    /// The number of variants matched to an ABC product, whether they change or not
+    pub variants_matched: usize,
+}
+
+impl PlanTotals {
+    /// Add up the changes in a plan
+    ///
+    /// # Arguments
+    ///
+    /// * `plan` - The `Plan` to add up
+    pub fn new(plan: &Plan) -> Self {
+        let mut variants = HashSet::new();
+        let mut zeroed = HashSet::new();
+        let mut price_changes = 0;
+        let mut total_percent = 0.0;
+        let mut max_percent: f64 = 0.0;
+
+        for change in plan.changes.iter() {
+            variants.insert(change.variant_id.as_str());
+            let prices = match change.change {
+                // A variant matched by UPC gets a price change that only replaces its SKU. Its price
+                // does not change, so it would only pull the average down
+                Change::Price { before, after } if before != after => Some((before, after)),
+                // A fixed price that is added or removed has no percent change. It follows the
+                // base price, whose change is already counted
+                Change::MarketPrice {
+                    before: Some(before),
+                    after: Some(after),
+                    ..
+                } => Some((before, after)),
+                Change::Inventory { before, after, .. } if before > 0 && after == 0 => {
+                    zeroed.insert(change.variant_id.as_str());
+                    None
+                }
+                _ => None,
+            };
+            if let Some((before, after)) = prices {
+                // A price of zero cannot be changed by a percentage, so count it as all of it
+                let percent = if before == 0 {
+                    100.0
+                } else {
+                    (after - before).abs() as f64 / before as f64 * 100.0
+                };
+                price_changes += 1;
+                total_percent += percent;
+                max_percent = max_percent.max(percent);
+            }
+        }
+
+        PlanTotals {
+            variants_changed: variants.len(),
+            price_changes,
+            average_price_change_percent: if price_changes == 0 {
+                0.0
+            } else {
+                total_percent / price_changes as f64
+            },
+            max_price_change_percent: max_percent,
+            zeroed_stock: zeroed.len(),
+            variants_matched: plan.variants_matched,
+        }
+    }
+
+    /// The share of the matched variants that go to zero stock, in percent. `None` if the plan
+    /// does not record how many variants were matched
+    pub fn zeroed_stock_percent(&self) -> Option<f64> {
+        if self.variants_matched == 0 {
+            None
+        } else {
+            Some(self.zeroed_stock as f64 / self.variants_matched as f64 * 100.0)
+        }
+    }
+}
+
+impl std::fmt::Display for PlanTotals {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} OF {} MATCHED VARIANTS CHANGED, {} PRICE CHANGES AVERAGING {:.2}% WITH THE LARGEST {:.2}%, {} VARIANTS GOING TO ZERO STOCK",
+            self.variants_changed,
+            self.variants_matched,
+            self.price_changes,
+            self.average_price_change_percent,
+            self.max_price_change_percent,
+            self.zeroed_stock
+        )
+    }
+}
+
+impl Guardrails {
+    /// Check the totals of a plan against every configured limit
+    ///
+    /// # Arguments
+    ///
+    /// * `totals` - The `PlanTotals` of the plan about to be applied
+    ///
+    /// # Returns
+    ///
+    /// A description of every limit that is exceeded. Empty if the plan is safe to apply
+    pub fn check(&self, totals: &PlanTotals) -> Vec<String> {
+        let mut exceeded = Vec::new();
+        if let Some(max) = self.max_variants_changed {
+            if totals.variants_changed > max {
+                exceeded.push(format!(
+                    "{} variants would change, more than the limit of {}",
+                    totals.variants_changed, max
+                ));
+            }
+        }
+        if let Some(max) = self.max_average_price_change_percent {
+            if totals.average_price_change_percent > max {
+                exceeded.push(format!(
+                    "Prices would change by {:.2}% on average, more than the limit of {}%",
+                    totals.average_price_change_percent, max
+                ));
+            }
+        }
+        if let Some(max) = self.max_price_change_percent {
+            if totals.max_price_change_percent > max {
+                exceeded.push(format!(
+                    "A price would change by {:.2}%, more than the limit of {}%",
+                    totals.max_price_change_percent, max
+                ));
+            }
+        }
+        if let Some(max) = self.max_zeroed_stock {
+            if totals.zeroed_stock > max {
+                exceeded.push(format!(
+                    "{} variants would go to zero stock, more than the limit of {}",
+                    totals.zeroed_stock, max
+                ));
+            }
+        }
+        if let (Some(max), Some(percent)) =
+            (self.max_zeroed_stock_percent, totals.zeroed_stock_percent())
+        {
+            if percent > max {
+                exceeded.push(format!(
+                    "{:.2}% of the matched variants would go to zero stock, more than the limit of {}%",
+                    percent, max
+                ));
+            }
+        }
+        exceeded
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::money::Currency;
+    use crate::plan::PlannedChange;
+
+    fn planned(variant: &str, change: Change) -> PlannedChange {
+        PlannedChange {
+            sku: variant.to_string(),
+            shopify_sku: variant.to_string(),
+            variant_id: variant.to_string(),
+            product_id: "gid://shopify/Product/1".to_string(),
+            inventory_item_id: "gid://shopify/InventoryItem/1".to_string(),
+            reason: String::new(),
+            rule: None,
+            change,
+        }
+    }
+
+    fn plan(changes: Vec<PlannedChange>) -> Plan {
+        Plan {
+            created_at: "2024-10-01 12:00:00".to_string(),
+            currency: Currency::USD,
+            variants_matched: 10,
+            changes,
+        }
+    }
+
+    fn market_price(before: Option<i64>, after: Option<i64>) -> Change {
+        Change::MarketPrice {
+            price_list_id: "gid://shopify/PriceList/1".to_string(),
+            currency: "CAD".parse().unwrap(),
+            before,
+            after,
+        }
+    }
+
+    #[test]
+    fn market_price_changes_count_as_price_changes() {
+        let totals = PlanTotals::new(&plan(vec![
+            planned(
+                "A1",
+                Change::Price {
+                    before: 1000,
+                    after: 900,
+                },
+            ),
+            planned("A1", market_price(Some(1360), Some(680))),
+        ]));
+        assert_eq!(totals.variants_changed, 1);
+        assert_eq!(totals.price_changes, 2);
+        assert_eq!(totals.max_price_change_percent, 50.0);
+        assert_eq!(totals.average_price_change_percent, 30.0);
+    }
+
+    #[test]
+    fn added_market_price_has_no_percent_change() {
+        let totals = PlanTotals::new(&plan(vec![planned("A1", market_price(None, Some(1360)))]));
+        assert_eq!(totals.variants_changed, 1);
+        assert_eq!(totals.price_changes, 0);
+        assert_eq!(totals.max_price_change_percent, 0.0);
+    }
+
+    #[test]
+    fn sku_only_price_change_is_not_averaged() {
+        let totals = PlanTotals::new(&plan(vec![
+            planned(
+                "A1",
+                Change::Price {
+                    before: 1000,
+                    after: 1200,
+                },
+            ),
+            planned(
+                "B2",
+                Change::Price {
+                    before: 1000,
+                    after: 1000,
+                },
+            ),
+        ]));
+        assert_eq!(totals.variants_changed, 2);
+        assert_eq!(totals.price_changes, 1);
+        assert_eq!(totals.average_price_change_percent, 20.0);
+    }
+
+    fn inventory(before: i64, after: i64) -> Change {
+        Change::Inventory {
+            location_id: "gid://shopify/Location/1".to_string(),
+            before,
+            after,
+        }
+    }
+
+    #[test]
+    fn totals_count_variants_prices_and_zeroed_stock() {
+        let totals = PlanTotals::new(&plan(vec![
+            planned(
+                "A1",
+                Change::Price {
+                    before: 1000,
+                    after: 1100,
+                },
+            ),
+            planned("A1", inventory(5, 0)),
+            planned("B2", inventory(3, 0)),
+            planned("B2", inventory(0, 0)),
+            planned("C3", inventory(0, 4)),
+            planned(
+                "D4",
+                Change::Price {
+                    before: 0,
+                    after: 500,
+                },
+            ),
+        ]));
+        assert_eq!(totals.variants_changed, 4);
+        assert_eq!(totals.price_changes, 2);
+        assert_eq!(totals.average_price_change_percent, 55.0);
+        assert_eq!(totals.max_price_change_percent, 100.0);
+        assert_eq!(totals.zeroed_stock, 2);
+    }
+
+    #[test]
+    fn check_lists_every_exceeded_limit() {
+        let totals = PlanTotals {
+            variants_changed: 10,
+            price_changes: 4,
+            average_price_change_percent: 12.0,
+            max_price_change_percent: 40.0,
+            zeroed_stock: 3,
+            variants_matched: 100,
+        };
+        let guardrails = Guardrails {
+            max_variants_changed: Some(9),
+            max_average_price_change_percent: Some(12.0),
+            max_price_change_percent: Some(30.0),
+            max_zeroed_stock: Some(2),
+            max_zeroed_stock_percent: Some(2.0),
+        };
+        let exceeded = guardrails.check(&totals);
+        assert_eq!(exceeded.len(), 4);
+        assert!(exceeded[0].starts_with("10 variants would change"));
+        assert!(exceeded[1].starts_with("A price would change by 40.00%"));
+        assert!(exceeded[2].starts_with("3 variants would go to zero stock"));
+        assert!(exceeded[3].starts_with("3.00% of the matched variants"));
+    }
+
+    #[test]
+    fn check_passes_within_the_limits() {
+        let totals = PlanTotals {
+            variants_changed: 10,
+            price_changes: 4,
+            average_price_change_percent: 12.0,
+            max_price_change_percent: 40.0,
+            zeroed_stock: 3,
+            variants_matched: 100,
+        };
+        assert!(Guardrails::default().check(&totals).is_empty());
+        let guardrails = Guardrails {
+            max_variants_changed: Some(10),
+            max_average_price_change_percent: Some(12.0),
+            max_price_change_percent: Some(40.0),
+            max_zeroed_stock: Some(3),
+            max_zeroed_stock_percent: Some(3.0),
+        };
+        assert!(guardrails.check(&totals).is_empty());
+    }
+
+    #[test]
+    fn default_check_stops_a_run_zeroing_most_stock() {
+        let changes = (0..5)
+            .map(|i| planned(&format!("A{}", i), inventory(5, 0)))
+            .collect();
+        let totals = PlanTotals::new(&plan(changes));
+        assert_eq!(totals.zeroed_stock_percent(), Some(50.0));
+
+        let exceeded = Guardrails::default().check(&totals);
+        assert_eq!(exceeded.len(), 1);
+        assert!(exceeded[0].starts_with("50.00% of the matched variants"));
+    }
+
+    #[test]
+    fn zeroed_stock_share_is_not_checked_without_matched_variants() {
+        let totals = PlanTotals {
+            zeroed_stock: 5,
+            ..PlanTotals::default()
+        };
+        assert_eq!(totals.zeroed_stock_percent(), None);
+        assert!(Guardrails::default().check(&totals).is_empty());
+    }
+
+    #[test]
+    fn default_limits_are_turned_off_with_null() {
+        let guardrails: Guardrails = serde_json::from_str(
+            r#"{ "max_average_price_change_percent": null, "max_zeroed_stock_percent": null }"#,
+        )
+        .unwrap();
+        assert_eq!(guardrails.max_average_price_change_percent, None);
+        assert_eq!(guardrails.max_zeroed_stock_percent, None);
+    }
+}
@@ -0,0 +1,327 @@
+use crate::money::Currency;
+use crate::plan::{Change, Plan, PlannedChange};
+use crate::FixerError;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+
+/// An append-only record of every change sent to Shopify, with the values it replaced, so a run
+/// can be undone. Stored as one JSON `JournalEntry` per line
+#[derive(Debug, Clone)]
+pub struct Journal {
+    path: PathBuf,
+    run_id: String,
+}
+
+/// One change as it was about to be sent to Shopify
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct JournalEntry {
+    /// The run the change was sent in
+    pub run_id: String,
+
+    /// When the change was recorded, just before it was sent. Like "2024-10-01 12:00:00"
+    pub recorded_at: String,
+
+    /// The change, holding the values Shopify had just before it was sent
+    #[serde(flatten)]
+    pub change: PlannedChange,
+}
+
+impl Journal {
+    /// Open the journal for a new run. The run ID is the current time to the millisecond, like
+    /// "20241001120000123", so runs started within the same second get different IDs
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The path of the journal file. Created when the first entry is recorded
+    pub fn new(path: &Path) -> Self {
+        Journal {
+            path: path.to_owned(),
+            run_id: format!("{}", chrono::Utc::now().format("%Y%m%d%H%M%S%3f")),
+        }
+    }
+
+    /// The ID of the run this journal records, to pass to `undo --run`
+    pub fn run_id(&self) -> &str {
+        &self.run_id
+    }
+
+    /// Append changes to the journal. Must be called before the changes are sent
+    ///
+    /// # Arguments
+    ///
+    /// * `changes` - The changes about to be sent, holding the values Shopify has right now
+    ///
+    /// # Errors
+    ///
+    /// Returns `FixerError::Custom` if the journal file cannot be opened or written to. The changes
+    /// must not be sent in that case, since they could not be undone
+    pub fn record(&self, changes: &[&PlannedChange]) -> Result<(), FixerError> {
+        let recorded_at = format!("{}", chrono::Utc::now().format("%Y-%m-%d %H:%M:%S"));
+        let mut lines = String::new();
+        for change in changes {
+            let entry = JournalEntry {
+                run_id: self.run_id.to_owned(),
+                recorded_at: recorded_at.to_owned(),
+                change: (*change).clone(),
+            };
+            lines.push_str(&serde_json::to_string(&entry)?);
+            lines.push('\n');
+        }
+
+        let write_err = |e: std::io::Error| {
+            FixerError::Custom(format!(
+                "Could not write journal file {:?}: {}",
+                &self.path, e
+            ))
+        };
+        let mut journal_file = fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .map_err(write_err)?;
+        journal_file
+            .write_all(lines.as_bytes())
+            .map_err(write_err)?;
+        journal_file.sync_data().map_err(write_err)?;
+        Ok(())
+    }
+}
+
+/// Read every entry recorded for one run
+///
+/// # Arguments
+///
+/// * `path` - The path of the journal file
+/// * `run_id` - The ID of the run to read
+///
+/// # Errors
+///
+/// Returns `FixerError::Custom` if the journal cannot be read or a line cannot be parsed
+pub fn read_run(path: &Path, run_id: &str) -> Result<Vec<JournalEntry>, FixerError> {
+    let journal_file = fs::File::open(path).or(Err(FixerError::Custom(format!(
+        "Could not read journal file {:?}",
+        path
+    ))))?;
+
+    let mut entries = Vec::new();
+    for (i, line) in BufReader::new(journal_file).lines().enumerate() {
+        let line = line.or(Err(FixerError::Custom(format!(
+            "Could not read line {} of journal file {:?}",
+            i + 1,
+            path
+        ))))?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let entry: JournalEntry = serde_json::from_str(&line).map_err(|e| {
+            FixerError::Custom(format!(
+                "Cannot parse line {} of journal file {:?}: {}",
+                i + 1,
+                path,
+                e
+            ))
+        })?;
+        if entry.run_id == run_id {
+            entries.push(entry);
+        }
+    }
+    Ok(entries)
+}
+
+/// Build a plan that restores the values a run replaced
+///
+/// # Arguments
+///
+/// * `entries` - The journal entries of the run to undo
+/// * `currency` - The currency of every price and cost in the journal
+///
+/// # Returns
+///
+/// The `Plan` that undoes the run, and the entries that cannot be undone. A unit cost that did not
+/// exist before the run cannot be removed again
+pub fn undo_plan(entries: &[JournalEntry], currency: Currency) -> (Plan, Vec<JournalEntry>) {
+    let mut plan = Plan {
+        created_at: format!("{}", chrono::Utc::now().format("%Y-%m-%d %H:%M:%S")),
+        currency,
+        variants_matched: 0,
+        changes: Vec::new(),
+    };
+    let mut skipped = Vec::new();
+
+    for entry in entries {
+        let change = match &entry.change.change {
+            Change::Price { before, after } => Change::Price {
+                before: *after,
+                after: *before,
+            },
+            Change::Cost {
+                before: Some(before),
+                after,
+            } => Change::Cost {
+                before: Some(*after),
+                after: *before,
+            },
+            Change::Cost { before: None, .. } => {
+                skipped.push(entry.to_owned());
+                continue;
+            }
+            Change::Inventory {
+                location_id,
+                before,
+                after,
+            } => Change::Inventory {
+                location_id: location_id.to_owned(),
+                before: *after,
+                after: *before,
+            },
+            Change::CompareAt { before, after } => Change::CompareAt {
+                before: *after,
+                after: *before,
+            },
+            Change::MarketPrice {
+                price_list_id,
+                currency,
+                before,
+                after,
+            } => Change::MarketPrice {
+                price_list_id: price_list_id.to_owned(),
+                currency: *currency,
+                before: *after,
+                after: *before,
+            },
+        };
+        plan.changes.push(PlannedChange {
+            // Price changes set the SKU too, so put back the SKU the variant had before
+            sku: entry.change.shopify_sku.to_owned(),
+            shopify_sku: entry.change.sku.to_owned(),
+            variant_id: entry.change.variant_id.to_owned(),
+            product_id: entry.change.product_id.to_owned(),
+            inventory_item_id: entry.change.inventory_item_id.to_owned(),
+            reason: format!("Undo run {}", &entry.run_id),
+            rule: None,
+            change,
+        });
+    }
+
+    (plan, skipped)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(change: Change) -> JournalEntry {
+        JournalEntry {
+            run_id: "20241001120000123".to_string(),
+            recorded_at: "2024-10-01 12:00:00".to_string(),
+            change: PlannedChange {
+                sku: "A1".to_string(),
+                shopify_sku: "old-a1".to_string(),
+                variant_id: "gid://shopify/ProductVariant/1".to_string(),
+                product_id: "gid://shopify/Product/1".to_string(),
+                inventory_item_id: "gid://shopify/InventoryItem/1".to_string(),
+                reason: String::new(),
+                rule: Some("Everything".to_string()),
+                change,
+            },
+        }
+    }
+
+    #[test]
+    fn undo_swaps_before_and_after_and_puts_back_the_sku() {
+        let entries = [
+            entry(Change::Price {
+                before: 1000,
+                after: 1299,
+            }),
+            entry(Change::CompareAt {
+                before: None,
+                after: Some(1500),
+            }),
+            entry(Change::Inventory {
+                location_id: "gid://shopify/Location/1".to_string(),
+                before: 5,
+                after: 0,
+            }),
+            entry(Change::Cost {
+                before: Some(500),
+                after: 600,
+            }),
+            entry(Change::MarketPrice {
+                price_list_id: "gid://shopify/PriceList/1".to_string(),
+                currency: "CAD".parse().unwrap(),
+                before: None,
+                after: Some(1767),
+            }),
+        ];
+        let (plan, skipped) = undo_plan(&entries, Currency::USD);
+
+        assert!(skipped.is_empty());
+        let changes: Vec<&Change> = plan.changes.iter().map(|c| &c.change).collect();
+        assert_eq!(
+            changes,
+            [
+                &Change::Price {
+                    before: 1299,
+                    after: 1000
+                },
+                &Change::CompareAt {
+                    before: Some(1500),
+                    after: None
+                },
+                &Change::Inventory {
+                    location_id: "gid://shopify/Location/1".to_string(),
+                    before: 0,
+                    after: 5
+                },
+                &Change::Cost {
+                    before: Some(600),
+                    after: 500
+                },
+                &Change::MarketPrice {
+                    price_list_id: "gid://shopify/PriceList/1".to_string(),
+                    currency: "CAD".parse().unwrap(),
+                    before: Some(1767),
+                    after: None
+                },
+            ]
+        );
+        assert_eq!(plan.changes[0].sku, "old-a1");
+        assert_eq!(plan.changes[0].shopify_sku, "A1");
+        assert_eq!(plan.changes[0].reason, "Undo run 20241001120000123");
+    }
+
+    #[test]
+    fn undo_skips_unit_costs_that_did_not_exist() {
+        let entries = [entry(Change::Cost {
+            before: None,
+            after: 600,
+        })];
+        let (plan, skipped) = undo_plan(&entries, Currency::USD);
+        assert!(plan.changes.is_empty());
+        assert_eq!(skipped.len(), 1);
+    }
+
+    #[test]
+    fn recorded_runs_are_read_back_by_id() {
+        let path = std::env::temp_dir().join(format!(
+            "shopify-price-fixer-{}-journal.jsonl",
+            std::process::id()
+        ));
+        let _ = fs::remove_file(&path);
+        let journal = Journal::new(&path);
+        let change = entry(Change::Price {
+            before: 1000,
+            after: 1299,
+        })
+        .change;
+        journal.record(&[&change]).unwrap();
+
+        let entries = read_run(&path, journal.run_id()).unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].change.change, change.change);
+        assert!(read_run(&path, "19700101000000000").unwrap().is_empty());
+    }
+}
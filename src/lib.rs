@@ -1,11 +1,25 @@
-use reqwest::header::{HeaderMap, InvalidHeaderValue, USER_AGENT};
+use reqwest::header::USER_AGENT;
 use std::collections::HashMap;
 use std::error::Error;
 use std::fs;
 use std::io::Write;
-use std::path::{Path, PathBuf};
-
+use std::path::PathBuf;
+use std::time::Duration;
+
+pub mod abc_columns;
+pub mod admin;
+pub mod guardrails;
+pub mod journal;
+pub mod map_prices;
+pub mod money;
+pub mod plan;
+pub mod price_lists;
 pub mod product;
+pub mod promotions;
+pub mod retry;
+pub mod rounding;
+pub mod rules;
+pub mod throttle;
 pub mod upc;
 
 #[derive(Debug)]
@@ -13,6 +27,46 @@ pub enum FixerError {
     Custom(String),
     SerdeJson(serde_json::Error),
     Reqwest(reqwest::Error),
+
+    /// The admin API answered with a status other than success
+    Http {
+        status: u16,
+        retry_after: Option<Duration>,
+    },
+
+    /// Shopify rejected a GraphQL request because the query cost bucket was empty
+    Throttled,
+
+    /// Shopify answered a GraphQL request with errors
+    Graphql(Vec<admin::GraphqlError>),
+
+    /// Shopify rejected a mutation and explained why in its `userErrors`
+    UserErrors(Vec<product::UserError>),
+
+    /// Every attempt allowed by the `RetryPolicy` failed. Holds the error of the last attempt
+    RetriesExhausted {
+        attempts: u32,
+        last: Box<FixerError>,
+    },
+}
+
+impl FixerError {
+    /// Whether the error is likely to pass if the request is sent again
+    pub fn is_transient(&self) -> bool {
+        match self {
+            FixerError::Reqwest(_) | FixerError::Throttled => true,
+            FixerError::Http { status, .. } => *status == 429 || *status >= 500,
+            _ => false,
+        }
+    }
+
+    /// How long Shopify asked to wait before sending the request again, if it did
+    pub fn retry_after(&self) -> Option<Duration> {
+        match self {
+            FixerError::Http { retry_after, .. } => *retry_after,
+            _ => None,
+        }
+    }
 }
 
 impl std::fmt::Display for FixerError {
@@ -35,36 +89,6 @@ impl From<serde_json::Error> for FixerError {
 
 impl std::error::Error for FixerError {}
 
-/// Initialize a reqwest client and its HeaderMap for sending HTTP requests
-///
-/// # Arguments
-///
-/// * `content-type` - The MIME type of the data being sent in the request. The price fixer only
-/// uses "application/json" and "application/graphql", but any valid contenty type should work
-///
-/// # Returns
-///
-/// If successful, return a tuple containing (reqwest::Client, reqwest::header::HeaderMap)
-///
-/// # Errors
-///
-/// * The thread will panic if the config file does not exist or is missing information
-/// * Will return Err(reqwest::header::InvalidHeaderValue) if `content-type` is an invalid MIME
-/// type or if the API_ACCESS_TOKEN cannot be parsed
-fn create_client_with_headers(
-    config: &Config,
-    content_type: &str,
-) -> Result<(reqwest::Client, HeaderMap), InvalidHeaderValue> {
-    let access_token = &config.shopify_access_token;
-
-    let client = reqwest::Client::new();
-    let mut headers = HeaderMap::new();
-    headers.insert("Content-Type", content_type.parse()?);
-    headers.insert("X-Shopify-Access-Token", access_token.parse()?);
-
-    Ok((client, headers))
-}
-
 /// Interfaces with the Shopify REST API to keep prices up to date with the proprietary ABC
 /// accounting software. This program will only change a Shopify price if the price in ABC is
 /// greater than what is currently in Shopify.
@@ -93,6 +117,20 @@ pub struct Cli {
     )]
     pub posted_data: String,
 
+    /// Optional. Which ABC export to read the items from. If left blank, assume "item-data"
+    #[arg(short, long, value_enum, default_value_t = AbcFormat::ItemData)]
+    pub format: AbcFormat,
+
+    /// The path to the "TabOutput.tsv" file generated by report 1-15. Only read when the format
+    /// is "tab-output". Usually C:\Users\User\Documents\My ABC Files\TabOutput.tsv
+    #[arg(short, long, default_value = "TabOutput.tsv")]
+    pub tab_output: String,
+
+    /// Set this to skip ABC rows that cannot be parsed instead of stopping. Every skipped row is
+    /// written to the rejected rows log with its row number, field and raw value
+    #[arg(short, long)]
+    pub lenient: bool,
+
     /// Optional. Path to the config.json file. If left blank, assume ./config.json
     #[arg(short, long, default_value = "./config.json")]
     pub config: PathBuf,
@@ -101,6 +139,72 @@ pub struct Cli {
     /// Shopify. Useful for debugging
     #[arg(short, long = "dry")]
     pub dry_run: bool,
+
+    /// Optional. Path to the journal file that records the previous value of everything the
+    /// fixer changes, so a run can be undone. If left blank, assume ./journal.jsonl
+    #[arg(short, long, default_value = "./journal.jsonl")]
+    pub journal: PathBuf,
+
+    /// Set this to fetch the Shopify catalog with a single Bulk Operation instead of paging through
+    /// it 250 variants at a time. Much faster for stores with tens of thousands of variants
+    #[arg(short, long)]
+    pub bulk: bool,
+
+    /// Optional. Path to a JSON rule file that sets the pricing policy per SKU prefix, vendor,
+    /// product type or tag. If left blank, prices are only ever raised to the ABC list price
+    #[arg(short, long)]
+    pub rules: Option<PathBuf>,
+
+    /// Optional. Path to a CSV file of scheduled promotions, with the headers "sku", "upc",
+    /// "price", "start" and "end". If left blank, no promotions are run
+    #[arg(long)]
+    pub promotions: Option<PathBuf>,
+
+    /// Optional. Path to a CSV file of minimum advertised prices, with the headers "sku", "upc"
+    /// and "map". No price is ever set below its MAP. If left blank, there is no MAP
+    #[arg(short, long)]
+    pub map: Option<PathBuf>,
+
+    /// Optional. Split the run into reviewing and changing Shopify. If left blank, plan and apply
+    /// the changes in one go
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+/// The ABC exports the items can be read from
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq)]
+pub enum AbcFormat {
+    /// "item.data" and "item_posted.data" from report 7-10
+    ItemData,
+
+    /// "TabOutput.tsv" from report 1-15
+    TabOutput,
+}
+
+/// The steps of a run that can be done separately
+#[derive(clap::Subcommand)]
+pub enum Command {
+    /// Compare ABC to Shopify and write every intended price, cost and inventory change to a plan
+    /// file. Nothing is changed in Shopify
+    Plan {
+        /// Where to write the plan file
+        #[arg(short, long, default_value = "./plan.json")]
+        out: PathBuf,
+    },
+
+    /// Send exactly the changes in a plan file written by the plan command to Shopify
+    Apply {
+        /// The plan file to apply
+        #[arg(default_value = "./plan.json")]
+        plan: PathBuf,
+    },
+
+    /// Restore the prices, costs and inventory that an earlier run changed, using the journal
+    Undo {
+        /// The ID of the run to undo. Printed in the summary at the end of every run
+        #[arg(short, long)]
+        run: String,
+    },
 }
 
 /// Stores configuration details to run the app. Inlcuding the api key and domain to send queries
@@ -116,8 +220,119 @@ pub struct Config {
     /// The publicly facing domain for the storefront. Like "mybusiness.com". Leave off scheme.
     pub storefront_url: String,
 
-    /// The version of the admin api to use. Such as "2022-07"
+    /// The version of the admin api to use for every query and mutation. Such as "2026-07"
     pub api_version: String,
+
+    /// Optional. The Shopify locations to sync stock to, and how much of the ABC stock each one
+    /// gets. If left blank, assume all of the ABC stock goes to `DEFAULT_LOCATION_ID`, the only
+    /// location synced before locations could be configured
+    #[serde(default = "default_locations")]
+    pub locations: Vec<StockLocation>,
+
+    /// Optional. How failed admin API requests are retried. If left blank, use the defaults of
+    /// `RetryPolicy`
+    #[serde(default)]
+    pub retry: retry::RetryPolicy,
+
+    /// Optional. The full base URL to send admin API requests to, including scheme and port. Like
+    /// "http://localhost:8080". Useful for pointing the fixer at a local mock server. If left
+    /// blank, assume "https://{business_url}"
+    #[serde(default)]
+    pub admin_base_url: Option<String>,
+
+    /// Optional. Where each field of the ABC item files is read from, and how it is parsed. If
+    /// left blank, assume the layout of report 7-10
+    #[serde(default)]
+    pub abc_columns: abc_columns::AbcColumns,
+
+    /// Optional. What to do with rows of "item_posted.data" whose SKU is not in "item.data". If
+    /// left blank, assume "warn"
+    #[serde(default)]
+    pub orphan_posted_rows: abc_columns::OrphanPolicy,
+
+    /// Optional. Limits on how much one run may change before it is aborted. If left blank, use
+    /// the defaults of `Guardrails`
+    #[serde(default)]
+    pub guardrails: guardrails::Guardrails,
+
+    /// Optional. The currency code of the store, which every ABC and Shopify price is in. If left
+    /// blank, assume "USD"
+    #[serde(default)]
+    pub currency: money::Currency,
+
+    /// Optional. The Shopify Markets price lists to keep fixed prices in, converted from the ABC
+    /// list price. If left blank, only the base price is set
+    #[serde(default)]
+    pub price_lists: Vec<price_lists::PriceList>,
+
+    /// Optional. The most seconds to wait for the bulk operation started by `--bulk` to finish
+    /// before the run stops with an error. If left blank, assume 3600
+    #[serde(default = "default_bulk_timeout_secs")]
+    pub bulk_timeout_secs: u64,
+}
+
+fn default_bulk_timeout_secs() -> u64 {
+    3600
+}
+
+/// The location stocked when `locations` is left out of "config.json"
+pub const DEFAULT_LOCATION_ID: &str = "gid://shopify/Location/5535957028";
+
+fn default_locations() -> Vec<StockLocation> {
+    vec![StockLocation {
+        id: DEFAULT_LOCATION_ID.to_string(),
+        stock: StockMapping::All,
+    }]
+}
+
+/// A Shopify inventory location that the fixer keeps stocked from ABC
+#[derive(Debug, Clone, serde::Deserialize)]
+pub struct StockLocation {
+    /// The Shopify ID of the location. Like "gid://shopify/Location/5535957028"
+    pub id: String,
+
+    /// Optional. How the ABC stock maps onto this location. If left blank, assume "all"
+    #[serde(default)]
+    pub stock: StockMapping,
+}
+
+/// How the stock of an ABC product is assigned to a Shopify location
+#[derive(Debug, Clone, Default, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StockMapping {
+    /// The location holds all of the ABC stock. `"all"`
+    #[default]
+    All,
+
+    /// The location holds a share of the ABC stock, rounded down. `{"fraction": 0.5}`
+    Fraction(f64),
+
+    /// The location is always set to zero. `"zero"`
+    Zero,
+}
+
+impl StockMapping {
+    /// Calculate the on hand quantity a location should have
+    ///
+    /// # Arguments
+    ///
+    /// * `abc_stock` - The stock of the product in ABC
+    ///
+    /// # Returns
+    ///
+    /// The quantity for the location. Never less than 0
+    pub fn quantity(&self, abc_stock: f64) -> i64 {
+        let quantity = match self {
+            StockMapping::All => abc_stock,
+            StockMapping::Fraction(f) => abc_stock * f,
+            StockMapping::Zero => 0.0,
+        };
+        if quantity > 0.0 {
+            quantity.floor() as i64
+        } else {
+            0
+        }
+    }
 }
 
 impl Config {
@@ -141,11 +356,56 @@ impl Config {
         };
         let config: Config = match serde_json::from_str(&config_str) {
             Ok(c) => c,
-            Err(_) => return Err("Failed to parse config file. Must define business_url, storefront_url, shopify_access_token, and api_version".to_string()),
+            Err(e) => return Err(format!("Failed to parse config file: {}. Must define business_url, storefront_url, shopify_access_token, and api_version", e)),
         };
+        if config.locations.is_empty() {
+            return Err(
+                "locations in config file is empty. List at least one location to sync stock to, \
+                 or leave locations out to use the default location"
+                    .to_string(),
+            );
+        }
+        if let Some(p) = config
+            .price_lists
+            .iter()
+            .find(|p| !(p.rate.is_finite() && p.rate > 0.0))
+        {
+            return Err(format!(
+                "The rate of price list {} must be above 0, not {}",
+                p.id, p.rate
+            ));
+        }
+        // Every amount is held in hundredths, so a currency like JPY or KWD would be priced wrong
+        if let Some(currency) = std::iter::once(config.currency)
+            .chain(config.price_lists.iter().map(|p| p.currency))
+            .find(|c| c.minor_units() != 2)
+        {
+            return Err(format!(
+                "{} has {} decimal places, and only currencies with 2 are supported",
+                currency,
+                currency.minor_units()
+            ));
+        }
 
         Ok(config)
     }
+
+    /// Build the full URL for a path under the admin API
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The path following "/admin/api/". Such as "2024-10/graphql.json"
+    ///
+    /// # Returns
+    ///
+    /// The URL built from `admin_base_url` if it is set, or from `business_url` otherwise
+    pub fn admin_url(&self, path: &str) -> String {
+        let base = match &self.admin_base_url {
+            Some(b) => b.trim_end_matches('/').to_string(),
+            None => format!("https://{}", self.business_url),
+        };
+        format!("{}/admin/api/{}", base, path.trim_start_matches('/'))
+    }
 }
 
 /// Fetches all Shopify products and returns a `HashMap` of SKU to tuple (price in cents, variant ID).
@@ -157,7 +417,7 @@ impl Config {
 /// # Returns
 ///
 /// * `Result<HashMap<String, (u32, u64)>, Box<dyn Error>>` - A mapping of SKU to tuple (price in
-/// cents, variant ID) if successful, error otherwise
+///   cents, variant ID) if successful, error otherwise
 ///
 /// # Errors
 ///
@@ -186,7 +446,7 @@ pub async fn all_shopify_products(
             None => break,
         };
 
-        if products_json.len() == 0 {
+        if products_json.is_empty() {
             break;
         }
 
@@ -210,9 +470,8 @@ pub async fn all_shopify_products(
                     None => continue,
                 };
 
-                let price_f32: f32 = price_str.parse()?;
-                let price_u32: u32 = (price_f32 * 100.0) as u32;
-                products.insert(sku, (price_u32, id));
+                let price = money::Money::parse(price_str, config.currency)?;
+                products.insert(sku, (u32::try_from(price.cents())?, id));
             }
         }
         page += 1;
@@ -243,6 +502,28 @@ pub enum Log {
 
     /// A list of different ABC products that have the same UPC
     DuplicateAbcUpcs,
+
+    /// Problems that do not stop the run, but should be looked at. "./warnings.txt"
+    Warning,
+
+    /// How many variants were updated and which updates failed, written at the end of a run.
+    /// "./summary.txt"
+    Summary,
+
+    /// Prices that sell below the margin floor over cost. "./below_margin_floor.txt"
+    MarginFloor,
+
+    /// Variants on sale in Shopify that were left alone. "./not_adjusted_on_sale.txt"
+    OnSale,
+
+    /// Prices found or decided below the minimum advertised price. "./map_violations.txt"
+    MapViolation,
+
+    /// ABC rows that were skipped because they cannot be parsed. "./rejected_rows.txt"
+    Rejected,
+
+    /// ABC items with no row in "item_posted.data", whose stock is taken as 0. "./not_posted.txt"
+    NotPosted,
 }
 
 /// Handles logging info to the proper file or stdout as specified.
@@ -250,10 +531,10 @@ pub enum Log {
 /// # Arguments
 ///
 /// * `to_stdout` - If `true`, don't write `msg` to any file, but instead write `msg` to stdout
-/// using `print!`. Otherwise, write `msg` to the appropriate log file specified by `log`
+///   using `print!`. Otherwise, write `msg` to the appropriate log file specified by `log`
 ///
 /// * `log` - Which `Log` `msg` should be written to. This will only be relevant if `to_stdout` is
-/// `false`
+///   `false`
 ///
 /// * `msg` - The message to be logged
 ///
@@ -294,6 +575,13 @@ where
         Log::Greater => log_path_parent.join("logs/not_adjusted_greater.txt"),
         Log::NotFound => log_path_parent.join("logs/not_found.txt"),
         Log::DuplicateAbcUpcs => log_path_parent.join("logs/duplicate_abc_upcs.txt"),
+        Log::Warning => log_path_parent.join("logs/warnings.txt"),
+        Log::Summary => log_path_parent.join("logs/summary.txt"),
+        Log::MarginFloor => log_path_parent.join("logs/below_margin_floor.txt"),
+        Log::OnSale => log_path_parent.join("logs/not_adjusted_on_sale.txt"),
+        Log::MapViolation => log_path_parent.join("logs/map_violations.txt"),
+        Log::Rejected => log_path_parent.join("logs/rejected_rows.txt"),
+        Log::NotPosted => log_path_parent.join("logs/not_posted.txt"),
     };
 
     if !log_path_parent.join("logs").exists() {
@@ -304,7 +592,60 @@ where
         .create(true)
         .append(true)
         .open(log_path)?;
-    log_file.write(format!("{} {}", now_formatted, msg_str).as_bytes())?;
+    log_file.write_all(format!("{} {}", now_formatted, msg_str).as_bytes())?;
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read_config_json(name: &str, json: &str) -> Result<Config, String> {
+        let path = std::env::temp_dir().join(format!(
+            "shopify-price-fixer-{}-{}",
+            std::process::id(),
+            name
+        ));
+        fs::write(&path, json).unwrap();
+        Config::read_config(&path)
+    }
+
+    const CONFIG: &str = r#""shopify_access_token": "token",
+        "business_url": "shop.myshopify.com",
+        "storefront_url": "shop.com",
+        "api_version": "2026-07""#;
+
+    #[test]
+    fn config_without_locations_stocks_the_default_location() {
+        let config = read_config_json("no-locations.json", &format!("{{ {} }}", CONFIG)).unwrap();
+
+        assert_eq!(config.locations.len(), 1);
+        assert_eq!(config.locations[0].id, DEFAULT_LOCATION_ID);
+        assert!(matches!(config.locations[0].stock, StockMapping::All));
+    }
+
+    #[test]
+    fn config_with_empty_locations_is_rejected() {
+        let json = format!("{{ {}, \"locations\": [] }}", CONFIG);
+        assert!(read_config_json("empty-locations.json", &json).is_err());
+    }
+
+    #[test]
+    fn config_with_a_currency_without_2_decimal_places_is_rejected() {
+        let json = format!("{{ {}, \"currency\": \"KWD\" }}", CONFIG);
+        assert!(read_config_json("kwd-store.json", &json).is_err());
+
+        let json = format!(
+            "{{ {}, \"price_lists\": [{{ \"id\": \"gid://shopify/PriceList/1\", \"currency\": \"JPY\", \"rate\": 150 }}] }}",
+            CONFIG
+        );
+        assert!(read_config_json("jpy-price-list.json", &json).is_err());
+
+        let json = format!(
+            "{{ {}, \"currency\": \"CAD\", \"price_lists\": [{{ \"id\": \"gid://shopify/PriceList/1\", \"currency\": \"EUR\" }}] }}",
+            CONFIG
+        );
+        assert!(read_config_json("eur-price-list.json", &json).is_ok());
+    }
+}
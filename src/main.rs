@@ -1,214 +1,18 @@
+use std::fs;
 use std::path::PathBuf;
-use std::{cmp, fs};
 
 use clap::Parser;
-use shopify_price_fixer::product::{
-    map_upcs, AbcProduct, ShopifyProduct, UpdateShopifyPriceResponse,
-};
-use shopify_price_fixer::{self as fixer, product, FixerError};
-
-use reqwest::header::HeaderMap;
-use reqwest::header::InvalidHeaderValue;
-
-/// Initialize a reqwest client and its HeaderMap for sending HTTP requests
-///
-/// # Arguments
-///
-/// * `content-type` - The MIME type of the data being sent in the request. The price fixer only
-/// uses "application/json" and "application/graphql", but any valid contenty type should work
-///
-/// # Returns
-///
-/// If successful, return a tuple containing (reqwest::Client, reqwest::header::HeaderMap)
-///
-/// # Errors
-///
-/// * The thread will panic if the config file does not exist or is missing information
-/// * Will return Err(reqwest::header::InvalidHeaderValue) if `content-type` is an invalid MIME
-/// type or if the API_ACCESS_TOKEN cannot be parsed
-fn create_client_with_headers(
-    config: &shopify_price_fixer::Config,
-    content_type: String,
-) -> Result<(reqwest::Client, HeaderMap), InvalidHeaderValue> {
-    let access_token = &config.shopify_access_token;
-
-    let client = reqwest::Client::new();
-    let mut headers = HeaderMap::new();
-    headers.insert("Content-Type", content_type.parse()?);
-    headers.insert("X-Shopify-Access-Token", access_token.parse()?);
-
-    Ok((client, headers))
-}
-
-/// Send a put request to a given shopify product to change its price
-///
-/// # Arguments
-///
-/// * `id` - The unique shopify id for the product to update
-/// * `new_price` - The value to set as the new price for the shopify item in cents. So $1.99 would
-/// be 199
-///
-/// # Returns
-///
-/// Will return the response text of the request
-///
-/// # Errors
-///
-/// Thread will panic if sending the request fails or if fetching the response fails
-async fn update_shopify_price(
-    config: &fixer::Config,
-    shopify_product: &ShopifyProduct,
-    abc_product: &AbcProduct,
-) -> Result<UpdateShopifyPriceResponse, FixerError> {
-    let (client, headers) = create_client_with_headers(config, "application/json".to_string()).or(
-        Err(FixerError::Custom(
-            "Found InvalidHeaderValue when updating shopify product".to_string(),
-        )),
-    )?;
-    let new_price = abc_product.list().max(shopify_product.price);
-    let query = serde_json::json!({
-        "query": r#"
-            mutation productVariantsBulkUpdate($productId: ID!, $variants: [ProductVariantsBulkInput!]!) { 
-                productVariantsBulkUpdate(productId: $productId, variants: $variants) { 
-                    product { 
-                        id 
-                        status 
-                    } 
-                    productVariants { 
-                        id 
-                        sku 
-                        price 
-                    } 
-                    userErrors {
-                        field 
-                        message 
-                    } 
-                }
-            };"#,
-        "variables": {
-            "productId": shopify_product.product_id,
-            "variants": [
-              {
-                "id": shopify_product.id,
-                "inventoryItem": {
-                    "sku": abc_product.sku(),
-                },
-                "price": format!("{:0.2}", (new_price as f64) / 100.0)
-              }
-            ],
-        }
-    });
-
-    let url = format!(
-        "https://{}/admin/api/{}/graphql.json",
-        config.business_url, config.api_version
-    );
-
-    let res = client
-        .post(url)
-        .headers(headers)
-        .body(query.to_string())
-        .send()
-        .await?
-        .text()
-        .await?;
-    Ok(serde_json::from_str(&res)?)
-}
-
-async fn update_shopify_inventory(
-    config: &fixer::Config,
-    shopify_product: &ShopifyProduct,
-    abc_product: &AbcProduct,
-) -> Result<(), FixerError> {
-    let (client, headers) = create_client_with_headers(config, "application/json".to_string()).or(
-        Err(FixerError::Custom(
-            "Found InvalidHeaderValue when updating shopify inventory".to_string(),
-        )),
-    )?;
-    let tracked_query = serde_json::json!({
-        "query": r#"
-            mutation inventoryItemUpdate($id: ID!, $input: InventoryItemInput!) {
-                inventoryItemUpdate(id: $id, input: $input) {
-                    inventoryItem {
-                        id 
-                        tracked 
-                        unitCost {
-                            amount
-                        }
-                    }
-                    userErrors {
-                        message
-                    }
-                }
-            }
-        "#,
-        "variables": {
-            "id": shopify_product.inventory_item_id,
-            "input": {
-                "tracked": true,
-                "cost": abc_product.cost() as f64 / 100.0
-            }
-        }
-    });
-    let query = serde_json::json!({
-        "query": r#"
-            mutation InventorySet($input: InventorySetQuantitiesInput!) {
-                inventorySetQuantities(input: $input) {
-                    inventoryAdjustmentGroup {
-                        createdAt
-                        changes {
-                            item {
-                                sku 
-                            }
-                            quantityAfterChange
-                        }
-                        reason
-                    }
-                    userErrors {
-                        message
-                    }
-                }
-            }"#,
-        "variables": {
-            "input": {
-                "ignoreCompareQuantity": true,
-                "name": "on_hand",
-                "reason": "correction",
-                "quantities": [{
-                    "inventoryItemId": shopify_product.inventory_item_id,
-                    "locationId": "gid://shopify/Location/5535957028",
-                    "quantity": if abc_product.stock() > 0.0 { abc_product.stock() as i64 } else { 0 },
-                }]
-            },
-        }
-    });
-
-    let url = format!(
-        "https://{}/admin/api/{}/graphql.json",
-        config.business_url, config.api_version
-    );
-
-    let tracked_res = client
-        .post(url.clone())
-        .headers(headers.clone())
-        .body(tracked_query.to_string())
-        .send()
-        .await?
-        .text()
-        .await?;
-    println!("{}", tracked_res);
-
-    let res = client
-        .post(url)
-        .headers(headers)
-        .body(query.to_string())
-        .send()
-        .await?
-        .text()
-        .await?;
-    println!("{}", res);
-    Ok(())
-}
+use shopify_price_fixer::abc_columns::{Rejects, Unmatched};
+use shopify_price_fixer::admin::{AdminClient, VersionSupport};
+use shopify_price_fixer::guardrails::PlanTotals;
+use shopify_price_fixer::journal::{self, Journal};
+use shopify_price_fixer::map_prices::MapList;
+use shopify_price_fixer::plan::{apply_plan, build_plan, Plan};
+use shopify_price_fixer::price_lists::FixedPrices;
+use shopify_price_fixer::product::ShopifyProduct;
+use shopify_price_fixer::promotions::Promotions;
+use shopify_price_fixer::rules::PricingRules;
+use shopify_price_fixer::{self as fixer, product, AbcFormat, Command, FixerError};
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
@@ -224,9 +28,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     };
 
     // Attempt to remove any existing log files. If no logs exist, ignore the resulting error
-    match fs::remove_dir_all(parent_dir.join("logs")) {
-        _ => (),
-    }
+    let _ = fs::remove_dir_all(parent_dir.join("logs"));
 
     let log_to_stdout = !cli.write_logs;
     let config = match shopify_price_fixer::Config::read_config(&cli.config) {
@@ -243,140 +45,349 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
             return Err(e)?;
         }
     };
-    let abc_products = match product::parse_abc_item_files(&item_data_path, &posted_data_path) {
-        Ok(p) => p,
+    let client = match AdminClient::new(&config) {
+        Ok(c) => c,
         Err(e) => {
             fixer::log(
                 log_to_stdout,
                 fixer::Log::Error,
-                format!(
-                    "Failed to parse abc products from data files with error: {}",
-                    e
-                ),
+                format!("Failed to build the admin API client with error: {}", e),
             )?;
             return Err(e)?;
         }
     };
-    let upc_map = map_upcs(&abc_products);
-    let (shopify_products, failed_nodes) = fixer::product::fetch_shopify_products(&config).await?;
-    for node in failed_nodes {
-        match ShopifyProduct::try_from(node) {
-            Ok(_) => continue,
-            Err(e) => fixer::log(
-                log_to_stdout,
-                fixer::Log::Error,
-                format!("FAILED NODE because of {}", e),
-            )?,
+    match client.version_support() {
+        VersionSupport::Supported => (),
+        VersionSupport::NearDeprecation(end) => fixer::log(
+            log_to_stdout,
+            fixer::Log::Warning,
+            format!(
+                "API version {} is close to deprecation. Shopify stops supporting it on {}. Update api_version in the config file",
+                client.api_version(),
+                end
+            ),
+        )?,
+        VersionSupport::Unsupported(end) => {
+            let msg = format!(
+                "API version {} is no longer supported by Shopify as of {}. Update api_version in the config file",
+                client.api_version(),
+                end
+            );
+            fixer::log(log_to_stdout, fixer::Log::Error, &msg)?;
+            return Err(FixerError::Custom(msg).into());
+        }
+        VersionSupport::Unreleased(release) => {
+            let msg = format!(
+                "API version {} will not be released until {}. Update api_version in the config file",
+                client.api_version(),
+                release
+            );
+            fixer::log(log_to_stdout, fixer::Log::Error, &msg)?;
+            return Err(FixerError::Custom(msg).into());
         }
     }
 
-    for shopify_product in shopify_products {
-        if !&shopify_product.is_active {
-            continue;
+    let plan = match &cli.command {
+        Some(Command::Apply { plan }) => match Plan::read(plan) {
+            Ok(p) => p,
+            Err(e) => {
+                fixer::log(
+                    log_to_stdout,
+                    fixer::Log::Error,
+                    format!("Failed to read plan file {:?} with error: {}", &plan, e),
+                )?;
+                return Err(e)?;
+            }
+        },
+        Some(Command::Undo { run }) => {
+            let entries = match journal::read_run(&cli.journal, run) {
+                Ok(e) => e,
+                Err(e) => {
+                    fixer::log(
+                        log_to_stdout,
+                        fixer::Log::Error,
+                        format!("Failed to read journal with error: {}", e),
+                    )?;
+                    return Err(e)?;
+                }
+            };
+            if entries.is_empty() {
+                let msg = format!(
+                    "No changes were recorded for run {} in {:?}",
+                    run, &cli.journal
+                );
+                fixer::log(log_to_stdout, fixer::Log::Error, &msg)?;
+                return Err(FixerError::Custom(msg).into());
+            }
+            let (plan, skipped) = journal::undo_plan(&entries, config.currency);
+            for entry in skipped {
+                fixer::log(
+                    log_to_stdout,
+                    fixer::Log::Warning,
+                    format!(
+                        "CANNOT UNDO {} because the value before run {} is unknown",
+                        &entry.change, &entry.run_id
+                    ),
+                )?;
+            }
+            plan
         }
-
-        let abc_product = match abc_products.get(&shopify_product.sku) {
-            Some(p) => p,
-            None => {
-                let barcode = match &shopify_product.barcode {
-                    Some(u) => u.to_string(),
-                    None => "".to_string(),
-                };
-                match upc_map.get(&barcode) {
-                    Some((dup, product)) => {
-                        if *dup {
-                            fixer::log(
-                                log_to_stdout,
-                                fixer::Log::DuplicateAbcUpcs,
-                                format!("DUPLICATE UPC {:?}", &shopify_product),
-                            )?;
-                            continue;
-                        } else {
-                            product
-                        }
+        _ => {
+            let rules = match &cli.rules {
+                Some(path) => match PricingRules::read(path) {
+                    Ok(r) => r,
+                    Err(e) => {
+                        fixer::log(
+                            log_to_stdout,
+                            fixer::Log::Error,
+                            format!("Failed to read pricing rules with error: {}", e),
+                        )?;
+                        return Err(e)?;
                     }
-                    None => {
+                },
+                None => PricingRules::default(),
+            };
+            let promotions = match &cli.promotions {
+                Some(path) => match Promotions::read(path, config.currency) {
+                    Ok(p) => p,
+                    Err(e) => {
                         fixer::log(
                             log_to_stdout,
-                            fixer::Log::NotFound,
-                            format!("NOT FOUND {:?}", &shopify_product),
+                            fixer::Log::Error,
+                            format!("Failed to read promotions with error: {}", e),
                         )?;
-                        continue;
+                        return Err(e)?;
                     }
+                },
+                None => Promotions::default(),
+            };
+            let map_list = match &cli.map {
+                Some(path) => match MapList::read(path, config.currency) {
+                    Ok(m) => m,
+                    Err(e) => {
+                        fixer::log(
+                            log_to_stdout,
+                            fixer::Log::Error,
+                            format!("Failed to read MAP list with error: {}", e),
+                        )?;
+                        return Err(e)?;
+                    }
+                },
+                None => MapList::default(),
+            };
+            let mut rejects = if cli.lenient {
+                Rejects::lenient()
+            } else {
+                Rejects::strict()
+            };
+            let mut unmatched = Unmatched::default();
+            let parsed = match cli.format {
+                AbcFormat::ItemData => product::parse_abc_item_files(
+                    &item_data_path,
+                    &posted_data_path,
+                    &config.abc_columns,
+                    config.currency,
+                    config.orphan_posted_rows,
+                    &mut rejects,
+                    &mut unmatched,
+                ),
+                AbcFormat::TabOutput => {
+                    if config.abc_columns.tab_output.cost.is_none() {
+                        fixer::log(
+                            log_to_stdout,
+                            fixer::Log::Warning,
+                            "NO COST COLUMN for TabOutput.tsv, unit costs are left alone, markup \
+                             keeps the Shopify price and the margin floor is not checked. Set \
+                             abc_columns.tab_output.cost in config.json to read costs",
+                        )?;
+                    }
+                    product::parse_abc_tab_output(
+                        &cli.tab_output,
+                        &config.abc_columns,
+                        config.currency,
+                        &mut rejects,
+                    )
                 }
+            };
+            let abc_products = match parsed {
+                Ok(p) => p,
+                Err(e) => {
+                    fixer::log(
+                        log_to_stdout,
+                        fixer::Log::Error,
+                        format!(
+                            "Failed to parse abc products from data files with error: {}",
+                            e
+                        ),
+                    )?;
+                    return Err(e)?;
+                }
+            };
+            for reject in rejects.rows() {
+                fixer::log(log_to_stdout, fixer::Log::Rejected, reject.to_string())?;
             }
-        };
-
-        let mut skip_price = false;
-        let mut skip_inventory = false;
-        if &shopify_product.sku.to_uppercase() == &abc_product.sku().to_uppercase() {
-            if &shopify_product.price == &abc_product.list() {
+            if !rejects.rows().is_empty() {
                 fixer::log(
                     log_to_stdout,
-                    fixer::Log::Equal,
+                    fixer::Log::Summary,
+                    format!("{} ABC ROWS REJECTED", rejects.rows().len()),
+                )?;
+            }
+            for (sku, row) in unmatched.orphans.iter() {
+                fixer::log(
+                    log_to_stdout,
+                    fixer::Log::Warning,
                     format!(
-                        "NOT ADJUSTING EQUAL {:?}, {:?}",
-                        &shopify_product, &abc_product
+                        "SKIPPED ORPHAN sku {} in row {} of item_posted.data has no item in \
+                         item.data",
+                        sku, row
                     ),
                 )?;
-                skip_price = true;
-            } else if &shopify_product.price > &abc_product.list() {
+            }
+            for sku in unmatched.unposted.iter() {
                 fixer::log(
                     log_to_stdout,
-                    fixer::Log::Greater,
+                    fixer::Log::NotPosted,
+                    format!("NOT POSTED {} has no row in item_posted.data", sku),
+                )?;
+            }
+            if !unmatched.unposted.is_empty() {
+                fixer::log(
+                    log_to_stdout,
+                    fixer::Log::Summary,
                     format!(
-                        "NOT ADJUSTING GREATER {:?}, {:?}",
-                        &shopify_product, &abc_product
+                        "{} ABC ITEMS NOT POSTED, THEIR STOCK IS TAKEN AS 0",
+                        unmatched.unposted.len()
                     ),
                 )?;
-                skip_price = true;
             }
-            if shopify_product.stock == abc_product.stock() as i64 {
-                skip_inventory = true;
+            let fetched = if cli.bulk {
+                fixer::product::fetch_shopify_products_bulk(&client, &config).await
+            } else {
+                fixer::product::fetch_shopify_products(&client, &config).await
+            };
+            let (mut shopify_products, failed_nodes) = match fetched {
+                Ok(f) => f,
+                Err(e) => {
+                    fixer::log(
+                        log_to_stdout,
+                        fixer::Log::Error,
+                        format!("Failed to fetch shopify products with error: {}", e),
+                    )?;
+                    return Err(e)?;
+                }
+            };
+            for node in failed_nodes {
+                match ShopifyProduct::from_node(node, config.currency) {
+                    Ok(_) => continue,
+                    Err(e) => fixer::log(
+                        log_to_stdout,
+                        fixer::Log::Error,
+                        format!("FAILED NODE because of {}", e),
+                    )?,
+                }
             }
+            let fixed_prices = match FixedPrices::fetch(&client, &config).await {
+                Ok(f) => f,
+                Err(e) => {
+                    fixer::log(
+                        log_to_stdout,
+                        fixer::Log::Error,
+                        format!("Failed to fetch price list prices with error: {}", e),
+                    )?;
+                    return Err(e)?;
+                }
+            };
+            fixed_prices.fill(&mut shopify_products);
+            build_plan(
+                &config,
+                &rules,
+                &promotions,
+                &map_list,
+                &abc_products,
+                &shopify_products,
+                log_to_stdout,
+            )?
         }
+    };
 
+    // Undoing a run only puts back what a run changed, so it is never held back by the guardrails
+    let totals = PlanTotals::new(&plan);
+    fixer::log(
+        log_to_stdout,
+        fixer::Log::Summary,
+        format!("PLAN {}", totals),
+    )?;
+    let exceeded = match &cli.command {
+        Some(Command::Undo { .. }) => Vec::new(),
+        _ => config.guardrails.check(&totals),
+    };
+    for limit in exceeded.iter() {
         fixer::log(
             log_to_stdout,
-            fixer::Log::Adjusted,
-            format!("ADJUSTING {:?}, {:?}", &shopify_product, &abc_product),
+            fixer::Log::Error,
+            format!("GUARDRAIL EXCEEDED {}", limit),
         )?;
+    }
 
-        // Dry run means that no prices should actually be changed, so skip the update step
-        if cli.dry_run {
-            continue;
-        }
-
-        if !skip_inventory {
-            if let Err(e) = update_shopify_inventory(&config, &shopify_product, &abc_product).await
-            {
-                fixer::log(
-                    log_to_stdout,
-                    fixer::Log::Error,
-                    format!(
-                        "ERROR updating inventory for product with id {:?}: {:?}",
-                        &shopify_product, e
-                    ),
-                )?;
-            }
-        }
+    if let Some(Command::Plan { out }) = &cli.command {
+        plan.write(out)?;
+        fixer::log(
+            log_to_stdout,
+            fixer::Log::Summary,
+            format!("WROTE {} CHANGES TO {:?}", plan.changes.len(), out),
+        )?;
+        return Ok(());
+    }
 
-        if !skip_price {
-            match update_shopify_price(&config, &shopify_product, &abc_product).await {
-                Ok(m) => {
-                    println!("{:?}", m);
-                }
+    if !exceeded.is_empty() {
+        return Err(FixerError::Custom(format!(
+            "Aborted without changing Shopify because the run exceeds {} guardrail(s): {}",
+            exceeded.len(),
+            exceeded.join("; ")
+        ))
+        .into());
+    }
 
-                Err(e) => fixer::log(
-                    log_to_stdout,
-                    fixer::Log::Error,
-                    format!(
-                        "ERROR updating product with id {:?}: {:?}",
-                        &shopify_product, e
-                    ),
-                )?,
-            }
+    // Dry run means that no prices should actually be changed, so skip the update step
+    if cli.dry_run {
+        for change in plan.changes.iter() {
+            fixer::log(
+                log_to_stdout,
+                fixer::Log::Adjusted,
+                format!("ADJUSTING {} BECAUSE {}", change, &change.reason),
+            )?;
         }
+        return Ok(());
+    }
+
+    let journal = Journal::new(&cli.journal);
+    let report = apply_plan(&client, &plan, &journal, log_to_stdout).await?;
+    fixer::log(
+        log_to_stdout,
+        fixer::Log::Summary,
+        format!("RUN ID {}", journal.run_id()),
+    )?;
+    fixer::log(
+        log_to_stdout,
+        fixer::Log::Summary,
+        format!("{} VARIANTS UPDATED", report.updated.len()),
+    )?;
+    fixer::log(
+        log_to_stdout,
+        fixer::Log::Summary,
+        format!("{} UPDATES REJECTED", report.rejected.len()),
+    )?;
+    for rejected in report.rejected {
+        fixer::log(log_to_stdout, fixer::Log::Summary, rejected)?;
+    }
+    fixer::log(
+        log_to_stdout,
+        fixer::Log::Summary,
+        format!("{} UPDATES FAILED AFTER RETRYING", report.failures.len()),
+    )?;
+    for failure in report.failures {
+        fixer::log(log_to_stdout, fixer::Log::Summary, failure)?;
     }
 
     Ok(())
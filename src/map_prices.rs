@@ -0,0 +1,214 @@
+use crate::money::{Currency, Money};
+use crate::product::{AbcProduct, ShopifyProduct};
+use crate::upc::Upc;
+use crate::FixerError;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::path::Path;
+
+/// Minimum advertised prices from vendors. No price the fixer writes, and no price it finds in
+/// Shopify, may be below the MAP of its item
+#[derive(Debug, Clone, Default)]
+pub struct MapList {
+    /// MAP keyed by uppercase SKU
+    by_sku: HashMap<String, Money>,
+
+    /// MAP keyed by UPC
+    by_upc: HashMap<String, Money>,
+}
+
+/// A row of a MAP file as it is written
+#[derive(Deserialize, Debug)]
+struct MapRow {
+    #[serde(default)]
+    sku: String,
+    #[serde(default)]
+    upc: String,
+    map: String,
+}
+
+impl MapList {
+    /// Read a MAP file. It is a CSV file with the headers "sku", "upc" and "map", and any other
+    /// columns are ignored. Each row needs a SKU or a UPC
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The path to the MAP file
+    /// * `currency` - The currency of every MAP in the file
+    ///
+    /// # Errors
+    ///
+    /// Returns `FixerError::Custom` if the file cannot be read or any row is not valid
+    pub fn read(path: &Path, currency: Currency) -> Result<Self, FixerError> {
+        let mut reader = csv::ReaderBuilder::new()
+            .trim(csv::Trim::All)
+            .from_path(path)
+            .map_err(|e| {
+                FixerError::Custom(format!("Could not read MAP file {:?}: {}", path, e))
+            })?;
+
+        let mut map_list = MapList::default();
+        for (i, row) in reader.deserialize::<MapRow>().enumerate() {
+            // Count the header as the first line
+            let line = i + 2;
+            let invalid = |field: &str| {
+                FixerError::Custom(format!(
+                    "Invalid {} on line {} of MAP file {:?}",
+                    field, line, path
+                ))
+            };
+            let row = row.map_err(|e| {
+                FixerError::Custom(format!(
+                    "Cannot parse line {} of MAP file {:?}: {}",
+                    line, path, e
+                ))
+            })?;
+
+            let map = Money::parse(&row.map, currency).map_err(|_| invalid("map"))?;
+            if row.sku.is_empty() && row.upc.is_empty() {
+                return Err(invalid("sku or upc"));
+            }
+            if !row.sku.is_empty() {
+                map_list.by_sku.insert(row.sku.to_uppercase(), map);
+            }
+            if !row.upc.is_empty() {
+                let upc = Upc::try_from(row.upc.as_str()).map_err(|_| invalid("upc"))?;
+                map_list.by_upc.insert(upc.to_string(), map);
+            }
+        }
+        Ok(map_list)
+    }
+
+    /// Find the MAP of a variant, by the ABC SKU, any ABC UPC or the Shopify barcode. When
+    /// several match, the highest MAP is used
+    ///
+    /// # Arguments
+    ///
+    /// * `shopify_product` - The Shopify variant
+    /// * `abc_product` - The ABC product matched to the variant
+    ///
+    /// # Returns
+    ///
+    /// The MAP, or `None` if the variant has none
+    pub fn price(
+        &self,
+        shopify_product: &ShopifyProduct,
+        abc_product: &AbcProduct,
+    ) -> Option<Money> {
+        let by_sku = self.by_sku.get(&abc_product.sku().to_uppercase()).copied();
+        abc_product
+            .upcs()
+            .iter()
+            .chain(shopify_product.barcode.iter())
+            .filter_map(|u| self.by_upc.get(&u.to_string()).copied())
+            .chain(by_sku)
+            .max_by_key(|m| m.cents())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::product::AbcProductBuilder;
+
+    fn usd(cents: i64) -> Money {
+        Money::new(cents, Currency::USD)
+    }
+
+    /// Write a MAP file to a temporary path and read it back
+    fn read_map(name: &str, csv: &str) -> Result<MapList, FixerError> {
+        let path = std::env::temp_dir().join(format!(
+            "shopify-price-fixer-{}-{}.csv",
+            std::process::id(),
+            name
+        ));
+        std::fs::write(&path, csv).unwrap();
+        MapList::read(&path, Currency::USD)
+    }
+
+    fn shopify(sku: &str, barcode: Option<&str>) -> ShopifyProduct {
+        ShopifyProduct {
+            id: "gid://shopify/ProductVariant/1".to_string(),
+            sku: sku.to_string(),
+            display_name: "Item".to_string(),
+            price: usd(1000),
+            compare_at_price: None,
+            barcode: barcode.map(|b| Upc::try_from(b).unwrap()),
+            available_for_sale: true,
+            inventory_item_id: "gid://shopify/InventoryItem/1".to_string(),
+            cost: None,
+            stock: HashMap::new(),
+            market_prices: HashMap::new(),
+            product_id: "gid://shopify/Product/1".to_string(),
+            is_active: true,
+            vendor: String::new(),
+            product_type: String::new(),
+            tags: Vec::new(),
+        }
+    }
+
+    fn abc(sku: &str, upcs: Vec<&str>) -> AbcProduct {
+        AbcProductBuilder::new()
+            .with_sku(sku)
+            .with_desc("Item")
+            .with_upcs(
+                upcs.into_iter()
+                    .map(|u| Upc::try_from(u).unwrap())
+                    .collect(),
+            )
+            .with_list(usd(1000))
+            .with_stock(1.0)
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn map_is_found_by_sku() {
+        let map_list = read_map("sku", "vendor,sku,upc,map\nAcme,a1,,12.50\n").unwrap();
+        assert_eq!(
+            map_list.price(&shopify("A1", None), &abc("A1", Vec::new())),
+            Some(usd(1250))
+        );
+        assert_eq!(
+            map_list.price(&shopify("B2", None), &abc("B2", Vec::new())),
+            None
+        );
+    }
+
+    #[test]
+    fn map_is_found_by_abc_upc_or_shopify_barcode() {
+        let map_list = read_map("upc", "sku,upc,map\n,012345678905,13.50\n").unwrap();
+        assert_eq!(
+            map_list.price(&shopify("A1", None), &abc("A1", vec!["012345678905"])),
+            Some(usd(1350))
+        );
+        assert_eq!(
+            map_list.price(&shopify("A1", Some("012345678905")), &abc("A1", Vec::new())),
+            Some(usd(1350))
+        );
+    }
+
+    #[test]
+    fn highest_map_wins() {
+        let map_list = read_map(
+            "highest",
+            "sku,upc,map\nA1,,12.00\n,012345678905,14.00\n,036000291452,13.00\n",
+        )
+        .unwrap();
+        let abc_product = abc("A1", vec!["036000291452"]);
+        assert_eq!(
+            map_list.price(&shopify("A1", Some("012345678905")), &abc_product),
+            Some(usd(1400))
+        );
+        assert_eq!(
+            map_list.price(&shopify("A1", None), &abc_product),
+            Some(usd(1300))
+        );
+    }
+
+    #[test]
+    fn map_file_with_a_bad_price_is_rejected() {
+        assert!(read_map("bad-price", "sku,upc,map\nA1,,twelve\n").is_err());
+        assert!(read_map("no-sku-or-upc", "sku,upc,map\n,,12.00\n").is_err());
+    }
+}
@@ -0,0 +1,375 @@
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::str::FromStr;
+
+/// Why an amount of money could not be parsed
+#[derive(Debug, Clone, PartialEq)]
+pub enum MoneyError {
+    /// There were no digits to parse
+    Empty,
+
+    /// The amount holds something other than a sign, digits, one '.', '$' or ','
+    Invalid(String),
+
+    /// The amount has fractions of a cent that are not zero, like "1.005"
+    TooPrecise(String),
+
+    /// The amount is too large to hold in cents
+    Overflow(String),
+
+    /// The currency is not a three letter code, like "USD"
+    InvalidCurrency(String),
+}
+
+impl std::fmt::Display for MoneyError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MoneyError::Empty => write!(f, "No amount to parse"),
+            MoneyError::Invalid(s) => write!(f, "{:?} is not an amount of money", s),
+            MoneyError::TooPrecise(s) => write!(f, "{:?} has fractions of a cent", s),
+            MoneyError::Overflow(s) => write!(f, "{:?} is too large", s),
+            MoneyError::InvalidCurrency(s) => write!(f, "{:?} is not a currency code", s),
+        }
+    }
+}
+
+impl std::error::Error for MoneyError {}
+
+/// An ISO 4217 currency code, like "USD"
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Currency([u8; 3]);
+
+impl Currency {
+    pub const USD: Currency = Currency(*b"USD");
+
+    pub fn code(&self) -> &str {
+        // Only ever built from three ASCII letters
+        std::str::from_utf8(&self.0).unwrap_or("???")
+    }
+
+    /// The number of digits after the decimal point in an amount of this currency, per ISO 4217.
+    /// Like 2 for "USD", 0 for "JPY" and 3 for "KWD"
+    pub fn minor_units(&self) -> u32 {
+        match self.code() {
+            "BIF" | "CLP" | "DJF" | "GNF" | "ISK" | "JPY" | "KMF" | "KRW" | "PYG" | "RWF"
+            | "UGX" | "UYI" | "VND" | "VUV" | "XAF" | "XOF" | "XPF" => 0,
+            "BHD" | "IQD" | "JOD" | "KWD" | "LYD" | "OMR" | "TND" => 3,
+            "CLF" | "UYW" => 4,
+            _ => 2,
+        }
+    }
+}
+
+impl Default for Currency {
+    fn default() -> Self {
+        Currency::USD
+    }
+}
+
+impl FromStr for Currency {
+    type Err = MoneyError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let code = s.trim().to_ascii_uppercase();
+        match <[u8; 3]>::try_from(code.as_bytes()) {
+            Ok(bytes) if bytes.iter().all(u8::is_ascii_uppercase) => Ok(Currency(bytes)),
+            _ => Err(MoneyError::InvalidCurrency(s.to_string())),
+        }
+    }
+}
+
+impl std::fmt::Display for Currency {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.code())
+    }
+}
+
+impl std::fmt::Debug for Currency {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.code())
+    }
+}
+
+impl Serialize for Currency {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.code())
+    }
+}
+
+impl<'de> Deserialize<'de> for Currency {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let code = String::deserialize(deserializer)?;
+        code.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+/// An exact amount of money in cents, in one currency. Serialized like Shopify's `MoneyV2`, as
+/// `{"amount": "12.99", "currencyCode": "USD"}`
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Money {
+    cents: i64,
+    currency: Currency,
+}
+
+impl Money {
+    pub fn new(cents: i64, currency: Currency) -> Self {
+        Money { cents, currency }
+    }
+
+    pub fn cents(&self) -> i64 {
+        self.cents
+    }
+
+    pub fn currency(&self) -> Currency {
+        self.currency
+    }
+
+    /// Parse an amount like "12.99", "-0.50", "$1,299.00" or "(5.00)" without going through a
+    /// float. Fractions of a cent are only allowed if they are zero, like "12.9900"
+    ///
+    /// # Arguments
+    ///
+    /// * `amount` - The amount to parse
+    /// * `currency` - The currency of the amount
+    ///
+    /// # Errors
+    ///
+    /// Returns a `MoneyError` if `amount` is not an exact amount of cents
+    pub fn parse(amount: &str, currency: Currency) -> Result<Self, MoneyError> {
+        Money::parse_with(amount, currency, false)
+    }
+
+    /// Parse an amount like `Money::parse`, but round fractions of a cent half up, away from zero.
+    /// "1.2345" is 1.23 and "1.235" is 1.24
+    ///
+    /// # Arguments
+    ///
+    /// * `amount` - The amount to parse
+    /// * `currency` - The currency of the amount
+    ///
+    /// # Errors
+    ///
+    /// Returns a `MoneyError` if `amount` is not an amount of money
+    pub fn parse_rounded(amount: &str, currency: Currency) -> Result<Self, MoneyError> {
+        Money::parse_with(amount, currency, true)
+    }
+
+    fn parse_with(amount: &str, currency: Currency, round: bool) -> Result<Self, MoneyError> {
+        Ok(Money {
+            cents: parse_cents_with(amount, round)?,
+            currency,
+        })
+    }
+
+    /// The amount the way Shopify expects it, like "12.99" or "-0.50"
+    pub fn amount(&self) -> String {
+        format_cents(self.cents)
+    }
+}
+
+/// Parse an amount from a rule file into cents, like `Money::parse`. Such amounts have no
+/// currency of their own, and are in the currency of whatever price they are compared with
+///
+/// # Errors
+///
+/// Returns a `MoneyError` if `amount` is not an exact amount of cents
+pub fn parse_cents(amount: &str) -> Result<i64, MoneyError> {
+    parse_cents_with(amount, false)
+}
+
+/// Format cents like `Money::amount`, for amounts from a rule file that have no currency of their
+/// own
+pub fn format_cents(cents: i64) -> String {
+    let sign = if cents < 0 { "-" } else { "" };
+    let abs = cents.unsigned_abs();
+    format!("{}{}.{:02}", sign, abs / 100, abs % 100)
+}
+
+fn parse_cents_with(amount: &str, round: bool) -> Result<i64, MoneyError> {
+    let invalid = || MoneyError::Invalid(amount.to_string());
+    let mut rest = amount.trim();
+
+    // Accounting style negatives, like "(5.00)"
+    let mut negative = false;
+    if let Some(inner) = rest.strip_prefix('(').and_then(|r| r.strip_suffix(')')) {
+        negative = true;
+        rest = inner.trim();
+    }
+    // The sign may come before or after a dollar sign, like "-$5.00" or "$-5.00"
+    for _ in 0..2 {
+        if let Some(r) = rest.strip_prefix('$') {
+            rest = r.trim_start();
+        } else if let Some(r) = rest.strip_prefix('-') {
+            negative = !negative;
+            rest = r.trim_start();
+        } else if let Some(r) = rest.strip_prefix('+') {
+            rest = r.trim_start();
+        }
+    }
+
+    let (whole, fraction) = match rest.split_once('.') {
+        Some((w, f)) => (w, f),
+        None => (rest, ""),
+    };
+    let whole: String = whole.chars().filter(|c| *c != ',').collect();
+    if whole.is_empty() && fraction.is_empty() {
+        return Err(MoneyError::Empty);
+    }
+    if !whole.chars().all(|c| c.is_ascii_digit()) || !fraction.chars().all(|c| c.is_ascii_digit()) {
+        return Err(invalid());
+    }
+    let round_up = if fraction.len() > 2 && fraction[2..].chars().any(|c| c != '0') {
+        if !round {
+            return Err(MoneyError::TooPrecise(amount.to_string()));
+        }
+        fraction.as_bytes()[2] >= b'5'
+    } else {
+        false
+    };
+
+    let overflow = || MoneyError::Overflow(amount.to_string());
+    let mut cents: i64 = 0;
+    let fraction_digits = fraction.chars().chain("00".chars()).take(2);
+    for digit in whole.chars().chain(fraction_digits) {
+        cents = cents
+            .checked_mul(10)
+            .and_then(|c| c.checked_add(digit as i64 - '0' as i64))
+            .ok_or_else(overflow)?;
+    }
+    if round_up {
+        cents = cents.checked_add(1).ok_or_else(overflow)?;
+    }
+
+    Ok(if negative { -cents } else { cents })
+}
+
+impl std::fmt::Display for Money {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.amount())
+    }
+}
+
+impl std::fmt::Debug for Money {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} {}", self.amount(), self.currency)
+    }
+}
+
+/// The shape of Shopify's `MoneyV2`, used to serialize `Money`
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct MoneyV2 {
+    amount: String,
+    currency_code: Currency,
+}
+
+impl Serialize for Money {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        MoneyV2 {
+            amount: self.amount(),
+            currency_code: self.currency,
+        }
+        .serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Money {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let money = MoneyV2::deserialize(deserializer)?;
+        Money::parse(&money.amount, money.currency_code).map_err(serde::de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    /// Any amount of money `Money::amount` can write. `i64::MIN` has no positive counterpart
+    fn any_money() -> impl Strategy<Value = Money> {
+        (-i64::MAX..=i64::MAX).prop_map(|cents| Money::new(cents, Currency::USD))
+    }
+
+    /// Write a positive number of cents with a comma between every three digits of dollars
+    fn with_commas(cents: u64) -> String {
+        let dollars = (cents / 100).to_string();
+        let mut grouped = String::new();
+        for (i, c) in dollars.chars().enumerate() {
+            if i > 0 && (dollars.len() - i).is_multiple_of(3) {
+                grouped.push(',');
+            }
+            grouped.push(c);
+        }
+        format!("{}.{:02}", grouped, cents % 100)
+    }
+
+    proptest! {
+        #[test]
+        fn amount_parses_back(m in any_money()) {
+            prop_assert_eq!(Money::parse(&m.amount(), Currency::USD), Ok(m));
+        }
+
+        #[test]
+        fn serde_round_trips(m in any_money()) {
+            let json = serde_json::to_string(&m).unwrap();
+            prop_assert_eq!(serde_json::from_str::<Money>(&json).unwrap(), m);
+        }
+
+        #[test]
+        fn signs_and_dollar_signs_are_read(cents in 0..=i64::MAX) {
+            let amount = Money::new(cents, Currency::USD).amount();
+            let negative = Money::new(-cents, Currency::USD);
+            for written in [
+                format!("-{}", amount),
+                format!("-${}", amount),
+                format!("$-{}", amount),
+                format!("({})", amount),
+                format!("(${})", amount),
+                format!(" ( {} ) ", amount),
+            ] {
+                prop_assert_eq!(Money::parse(&written, Currency::USD), Ok(negative), "{}", written);
+            }
+            for written in [format!("+{}", amount), format!("${}", amount), format!("$+{}", amount)] {
+                prop_assert_eq!(
+                    Money::parse(&written, Currency::USD),
+                    Ok(Money::new(cents, Currency::USD)),
+                    "{}",
+                    written
+                );
+            }
+        }
+
+        #[test]
+        fn commas_are_ignored(cents in 0..=i64::MAX as u64) {
+            prop_assert_eq!(
+                Money::parse(&with_commas(cents), Currency::USD),
+                Ok(Money::new(cents as i64, Currency::USD))
+            );
+        }
+
+        #[test]
+        fn fractions_of_a_cent_round_half_up(cents in 0..1_000_000_000i64, extra in 1..100u8) {
+            let amount = format!("{}{:02}", Money::new(cents, Currency::USD).amount(), extra);
+            let rounded = cents + i64::from(extra >= 50);
+            prop_assert_eq!(
+                Money::parse_rounded(&amount, Currency::USD),
+                Ok(Money::new(rounded, Currency::USD))
+            );
+            prop_assert_eq!(
+                Money::parse_rounded(&format!("-{}", amount), Currency::USD),
+                Ok(Money::new(-rounded, Currency::USD))
+            );
+            prop_assert_eq!(
+                Money::parse(&amount, Currency::USD),
+                Err(MoneyError::TooPrecise(amount.clone()))
+            );
+        }
+    }
+
+    #[test]
+    fn trailing_zero_fractions_are_exact() {
+        assert_eq!(
+            Money::parse("12.9900", Currency::USD),
+            Ok(Money::new(1299, Currency::USD))
+        );
+    }
+}
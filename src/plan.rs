@@ -0,0 +1,1162 @@
+use crate::admin::AdminClient;
+use crate::journal::Journal;
+use crate::map_prices::MapList;
+use crate::money::{format_cents, Currency, Money};
+use crate::price_lists::{fetch_fixed_prices, update_fixed_prices};
+use crate::product::{
+    fetch_variant_nodes, map_upcs, update_shopify_inventory, update_shopify_prices, AbcProduct,
+    LocationQuantity, Node, PriceUpdate, ShopifyProduct,
+};
+use crate::promotions::Promotions;
+use crate::rules::{FloorAction, PricingPolicy, PricingRules};
+use crate::{log, Config, FixerError, Log};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::Path;
+
+/// Every change the fixer intends to make to Shopify in one run. Written by the `plan` command so
+/// it can be reviewed, then sent exactly as written by the `apply` command
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct Plan {
+    /// When the plan was built. Like "2024-10-01 12:00:00"
+    pub created_at: String,
+
+    /// The currency of every price and cost in the plan. If left blank, assume "USD"
+    #[serde(default)]
+    pub currency: Currency,
+
+    /// The number of active Shopify variants matched to an ABC product, whether they change or
+    /// not. 0 in plans written before it was recorded, and in plans that undo a run
+    #[serde(default)]
+    pub variants_matched: usize,
+
+    pub changes: Vec<PlannedChange>,
+}
+
+/// A single change to one Shopify variant
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PlannedChange {
+    /// The SKU of the matching ABC product. Price changes also set the variant's SKU to this
+    pub sku: String,
+
+    /// The SKU of the variant in Shopify before the change
+    pub shopify_sku: String,
+
+    /// The Shopify ID of the variant
+    pub variant_id: String,
+
+    /// The Shopify ID of the product that owns the variant
+    pub product_id: String,
+
+    /// The Shopify ID of the inventory item of the variant
+    pub inventory_item_id: String,
+
+    /// Why the fixer wants to make this change
+    pub reason: String,
+
+    /// The name of the pricing rule that decided a price change
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rule: Option<String>,
+
+    #[serde(flatten)]
+    pub change: Change,
+}
+
+/// What changes about a variant, with its value before and after. Prices and costs are in cents of
+/// the plan currency
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Change {
+    Price {
+        before: i64,
+        after: i64,
+    },
+    Cost {
+        before: Option<i64>,
+        after: i64,
+    },
+    Inventory {
+        location_id: String,
+        before: i64,
+        after: i64,
+    },
+    CompareAt {
+        before: Option<i64>,
+        after: Option<i64>,
+    },
+
+    /// A fixed price in a Shopify Markets price list, in cents of the list's currency. `None`
+    /// means the variant has no fixed price in the list
+    MarketPrice {
+        price_list_id: String,
+        currency: Currency,
+        before: Option<i64>,
+        after: Option<i64>,
+    },
+}
+
+impl std::fmt::Display for PlannedChange {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.change {
+            Change::Price { before, after } => write!(
+                f,
+                "PRICE {} {} FROM {} TO {}",
+                &self.sku,
+                &self.variant_id,
+                format_cents(*before),
+                format_cents(*after)
+            ),
+            Change::Cost { before, after } => write!(
+                f,
+                "COST {} {} FROM {} TO {}",
+                &self.sku,
+                &self.variant_id,
+                before.map_or("NONE".to_string(), format_cents),
+                format_cents(*after)
+            ),
+            Change::Inventory {
+                location_id,
+                before,
+                after,
+            } => write!(
+                f,
+                "INVENTORY {} {} AT {} FROM {} TO {}",
+                &self.sku, &self.variant_id, location_id, before, after
+            ),
+            Change::CompareAt { before, after } => write!(
+                f,
+                "COMPARE AT {} {} FROM {} TO {}",
+                &self.sku,
+                &self.variant_id,
+                before.map_or("NONE".to_string(), format_cents),
+                after.map_or("NONE".to_string(), format_cents)
+            ),
+            Change::MarketPrice {
+                price_list_id,
+                currency,
+                before,
+                after,
+            } => write!(
+                f,
+                "MARKET PRICE {} {} IN {} FROM {} TO {} {}",
+                &self.sku,
+                &self.variant_id,
+                price_list_id,
+                before.map_or("NONE".to_string(), format_cents),
+                after.map_or("NONE".to_string(), format_cents),
+                currency
+            ),
+        }
+    }
+}
+
+/// The outcome of applying a `Plan`
+#[derive(Debug, Default)]
+pub struct ApplyReport {
+    /// The IDs of variants Shopify confirmed at least one change for
+    pub updated: HashSet<String>,
+
+    /// The changes that failed without being retried, because Shopify rejected them or did not
+    /// confirm them, or because they could not be journaled
+    pub rejected: Vec<String>,
+
+    /// The changes that still failed after every retry
+    pub failures: Vec<String>,
+}
+
+impl ApplyReport {
+    /// Record a change that failed, as rejected or as failed after retrying
+    ///
+    /// # Arguments
+    ///
+    /// * `change` - The change that failed
+    /// * `e` - Why it failed
+    fn fail(&mut self, change: &PlannedChange, e: &FixerError) {
+        let failure = format!("{}: {}", change, e);
+        match e {
+            FixerError::RetriesExhausted { .. } => self.failures.push(failure),
+            _ => self.rejected.push(failure),
+        }
+    }
+}
+
+impl Plan {
+    /// Read a plan file written by `Plan::write`
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The path to the plan file
+    ///
+    /// # Errors
+    ///
+    /// Returns `FixerError::Custom` if the file cannot be read and `FixerError::SerdeJson` if it
+    /// is not a valid plan
+    pub fn read(path: &Path) -> Result<Self, FixerError> {
+        let plan_str = fs::read_to_string(path).or(Err(FixerError::Custom(format!(
+            "Could not read plan file {:?}",
+            path
+        ))))?;
+        Ok(serde_json::from_str(&plan_str)?)
+    }
+
+    /// Write the plan as pretty printed JSON
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - Where to write the plan file. Any existing file is replaced
+    ///
+    /// # Errors
+    ///
+    /// Returns `FixerError::Custom` if the file cannot be written
+    pub fn write(&self, path: &Path) -> Result<(), FixerError> {
+        let plan_str = serde_json::to_string_pretty(self)?;
+        fs::write(path, plan_str).or(Err(FixerError::Custom(format!(
+            "Could not write plan file {:?}",
+            path
+        ))))
+    }
+}
+
+/// Compare ABC to Shopify and decide which prices, costs and on hand quantities should change.
+/// Nothing is sent to Shopify
+///
+/// # Arguments
+///
+/// * `config` - The `Config` holding the locations to stock
+/// * `rules` - The `PricingRules` that decide the price of each variant
+/// * `promotions` - The scheduled `Promotions` that override the price while they run
+/// * `map_list` - The `MapList` of minimum advertised prices no price may be below
+/// * `abc_products` - The ABC products keyed by SKU
+/// * `shopify_products` - Every variant fetched from Shopify
+/// * `log_to_stdout` - Passed to `log` for variants that are skipped
+///
+/// # Returns
+///
+/// The `Plan` of every change
+///
+/// # Errors
+///
+/// Will return `std::io::Error` if a log file could not be written
+pub fn build_plan(
+    config: &Config,
+    rules: &PricingRules,
+    promotions: &Promotions,
+    map_list: &MapList,
+    abc_products: &HashMap<String, AbcProduct>,
+    shopify_products: &[ShopifyProduct],
+    log_to_stdout: bool,
+) -> Result<Plan, std::io::Error> {
+    let upc_map = map_upcs(abc_products);
+    let now = chrono::Local::now().naive_local();
+    let mut plan = Plan {
+        created_at: format!("{}", chrono::Utc::now().format("%Y-%m-%d %H:%M:%S")),
+        currency: config.currency,
+        variants_matched: 0,
+        changes: Vec::new(),
+    };
+
+    for shopify_product in shopify_products {
+        if !&shopify_product.is_active {
+            continue;
+        }
+
+        let (abc_product, matched_by) = match abc_products.get(&shopify_product.sku) {
+            Some(p) => (p, "SKU"),
+            None => {
+                let barcode = match &shopify_product.barcode {
+                    Some(u) => u.to_string(),
+                    None => "".to_string(),
+                };
+                match upc_map.get(&barcode) {
+                    Some((dup, product)) => {
+                        if *dup {
+                            log(
+                                log_to_stdout,
+                                Log::DuplicateAbcUpcs,
+                                format!("DUPLICATE UPC {:?}", &shopify_product),
+                            )?;
+                            continue;
+                        } else {
+                            (product, "UPC")
+                        }
+                    }
+                    None => {
+                        log(
+                            log_to_stdout,
+                            Log::NotFound,
+                            format!("NOT FOUND {:?}", &shopify_product),
+                        )?;
+                        continue;
+                    }
+                }
+            }
+        };
+        plan.variants_matched += 1;
+
+        let planned = |reason: String, change: Change| PlannedChange {
+            sku: abc_product.sku(),
+            shopify_sku: shopify_product.sku.to_owned(),
+            variant_id: shopify_product.id.to_owned(),
+            product_id: shopify_product.product_id.to_owned(),
+            inventory_item_id: shopify_product.inventory_item_id.to_owned(),
+            reason,
+            rule: None,
+            change,
+        };
+
+        // A variant matched by UPC gets its SKU replaced by the ABC SKU, which is sent along with
+        // its price, so always plan a price change for it
+        let map_price = map_list.price(shopify_product, abc_product);
+        if let Some(m) = map_price.filter(|m| shopify_product.price.cents() < m.cents()) {
+            log(
+                log_to_stdout,
+                Log::MapViolation,
+                format!(
+                    "SHOPIFY PRICE {} BELOW MAP {} {:?}, {:?}",
+                    shopify_product.price, m, &shopify_product, &abc_product
+                ),
+            )?;
+        }
+        let abc_cost = abc_product
+            .cost()
+            .map_or("unknown".to_string(), |c| c.amount());
+        let decision = rules.decide(
+            shopify_product,
+            abc_product,
+            promotions.status(abc_product, now),
+            map_price,
+        );
+        if let Some(m) = decision.below_map {
+            log(
+                log_to_stdout,
+                Log::MapViolation,
+                format!(
+                    "RAISED TO MAP {} BY RULE {} {:?}, {:?}",
+                    m, &decision.rule, &shopify_product, &abc_product
+                ),
+            )?;
+        }
+        if let Some((floor, action)) = decision.below_floor {
+            log(
+                log_to_stdout,
+                Log::MarginFloor,
+                format!(
+                    "BELOW MARGIN FLOOR {} OVER COST {} BY RULE {}, {} {:?}, {:?}",
+                    floor,
+                    abc_cost,
+                    &decision.rule,
+                    match action {
+                        FloorAction::Raise => "RAISED TO FLOOR",
+                        FloorAction::Flag => "NOT RAISED",
+                    },
+                    &shopify_product,
+                    &abc_product
+                ),
+            )?;
+        }
+
+        let same_sku = shopify_product.sku.to_uppercase() == abc_product.sku().to_uppercase();
+        if same_sku && decision.price == shopify_product.price {
+            if decision.kept_sale {
+                log(
+                    log_to_stdout,
+                    Log::OnSale,
+                    format!(
+                        "NOT ADJUSTING ON SALE BY RULE {} {:?}, {:?}",
+                        &decision.rule, &shopify_product, &abc_product
+                    ),
+                )?;
+            } else if let Some(cost) = decision.unusable_cost {
+                log(
+                    log_to_stdout,
+                    Log::Warning,
+                    format!(
+                        "NOT MARKING UP COST {} BY RULE {} {:?}, {:?}",
+                        cost, &decision.rule, &shopify_product, &abc_product
+                    ),
+                )?;
+            } else if let Some(guarded) = &decision.guarded {
+                log(
+                    log_to_stdout,
+                    Log::Warning,
+                    format!(
+                        "NOT DECREASING BY RULE {} because {} {:?}, {:?}",
+                        &decision.rule, guarded, &shopify_product, &abc_product
+                    ),
+                )?;
+            } else {
+                let (log_type, kept) = if decision.policy == PricingPolicy::RaiseOnly
+                    && shopify_product.price.cents() > abc_product.list().cents()
+                {
+                    (Log::Greater, "GREATER")
+                } else {
+                    (Log::Equal, "EQUAL")
+                };
+                log(
+                    log_to_stdout,
+                    log_type,
+                    format!(
+                        "NOT ADJUSTING {} BY RULE {} {:?}, {:?}",
+                        kept, &decision.rule, &shopify_product, &abc_product
+                    ),
+                )?;
+            }
+        } else {
+            let mut reason = if let Some(promo) = &decision.promo {
+                format!(
+                    "Matched by {}. The {} is set instead of the regular price from rule {} ({}) with ABC list price {} and cost {}",
+                    matched_by,
+                    promo,
+                    &decision.rule,
+                    &decision.policy,
+                    abc_product.list(),
+                    abc_cost
+                )
+            } else if decision.price == shopify_product.price {
+                format!(
+                    "Matched by {}. Shopify SKU {} is replaced by ABC SKU {} and the price is kept",
+                    matched_by,
+                    &shopify_product.sku,
+                    abc_product.sku()
+                )
+            } else {
+                format!(
+                    "Matched by {}. Rule {} ({}) sets the price from {} with ABC list price {} and cost {}",
+                    matched_by,
+                    &decision.rule,
+                    &decision.policy,
+                    shopify_product.price,
+                    abc_product.list(),
+                    abc_cost
+                )
+            };
+            if let Some(rounding) = &decision.rounding {
+                reason.push_str(&format!(", {}", rounding));
+            }
+            if let Some(guarded) = &decision.guarded {
+                reason.push_str(&format!(". The {}", guarded));
+                // A sale that is cut runs at a smaller discount than ABC gives, so flag it
+                if let Some(promo) = &decision.promo {
+                    log(
+                        log_to_stdout,
+                        Log::Warning,
+                        format!(
+                            "PROMO CUT BY RULE {} because the {} {} {:?}, {:?}",
+                            &decision.rule, promo, guarded, &shopify_product, &abc_product
+                        ),
+                    )?;
+                }
+            }
+            if let Some((floor, FloorAction::Raise)) = decision.below_floor {
+                reason.push_str(&format!(". Raised to the margin floor {} over cost", floor));
+            }
+            if let Some(m) = decision.below_map {
+                reason.push_str(&format!(". Raised to the MAP {}", m));
+            }
+            let mut change = planned(
+                reason,
+                Change::Price {
+                    before: shopify_product.price.cents(),
+                    after: decision.price.cents(),
+                },
+            );
+            change.rule = Some(decision.rule.to_owned());
+            plan.changes.push(change);
+        }
+
+        let compare_at_price = shopify_product.compare_at_price;
+        if let Some(after) = decision.compare_at_price {
+            if after != compare_at_price {
+                let reason = match after {
+                    Some(regular) => format!(
+                        "Matched by {}. The compare-at price shows the regular price {} during the ABC promo",
+                        matched_by, regular
+                    ),
+                    None => match &decision.ended_promotion {
+                        Some(promotion) => format!(
+                            "Matched by {}. The {} has ended",
+                            matched_by, promotion
+                        ),
+                        None => format!(
+                            "Matched by {}. The Shopify sale is ended by the sale policy",
+                            matched_by
+                        ),
+                    },
+                };
+                let mut change = planned(
+                    reason,
+                    Change::CompareAt {
+                        before: compare_at_price.map(|c| c.cents()),
+                        after: after.map(|c| c.cents()),
+                    },
+                );
+                change.rule = Some(decision.rule.to_owned());
+                plan.changes.push(change);
+            }
+        }
+
+        for price_list in config.price_lists.iter() {
+            let before = shopify_product.market_prices.get(&price_list.id).copied();
+            let after = price_list.price(decision.price);
+            if before != Some(after.cents()) {
+                plan.changes.push(planned(
+                    format!(
+                        "Matched by {}. The price {} is converted by the {}",
+                        matched_by, decision.price, price_list
+                    ),
+                    Change::MarketPrice {
+                        price_list_id: price_list.id.to_owned(),
+                        currency: price_list.currency,
+                        before,
+                        after: Some(after.cents()),
+                    },
+                ));
+            }
+        }
+
+        if let Some(cost) = abc_product.cost() {
+            if shopify_product.cost.map(|c| c.cents()) != Some(cost.cents()) {
+                plan.changes.push(planned(
+                    format!(
+                        "Matched by {}. ABC cost {} differs from Shopify unit cost",
+                        matched_by, cost
+                    ),
+                    Change::Cost {
+                        before: shopify_product.cost.map(|c| c.cents()),
+                        after: cost.cents(),
+                    },
+                ));
+            }
+        }
+
+        // Only set stock at the configured locations the item is already stocked at
+        for location in config.locations.iter() {
+            let before = match shopify_product.stock.get(&location.id) {
+                Some(s) => *s,
+                None => continue,
+            };
+            let after = location.stock.quantity(abc_product.stock());
+            if before != after {
+                plan.changes.push(planned(
+                    format!(
+                        "Matched by {}. ABC stock {} maps to {} at this location",
+                        matched_by,
+                        abc_product.stock(),
+                        after
+                    ),
+                    Change::Inventory {
+                        location_id: location.id.to_owned(),
+                        before,
+                        after,
+                    },
+                ));
+            }
+        }
+    }
+
+    Ok(plan)
+}
+
+/// Read the values Shopify holds right now for changes about to be sent, since the variants may
+/// have changed after the plan was built
+///
+/// # Arguments
+///
+/// * `client` - The `AdminClient` to send the queries with
+/// * `changes` - The changes about to be sent
+/// * `currency` - The currency of every price and cost in the plan
+///
+/// # Returns
+///
+/// A copy of every change with its `before` and `shopify_sku` set to the live values
+///
+/// # Errors
+///
+/// * Will return `FixerError::Custom` if a variant no longer exists or a live price cannot be
+///   parsed
+/// * Will return a `FixerError` if sending a request fails or a response cannot be parsed
+async fn live_changes(
+    client: &AdminClient,
+    changes: &[&PlannedChange],
+    currency: Currency,
+) -> Result<Vec<PlannedChange>, FixerError> {
+    let mut variant_ids: Vec<&str> = Vec::new();
+    let mut location_ids: Vec<&str> = Vec::new();
+    let mut price_lists: Vec<(&str, Currency)> = Vec::new();
+    for change in changes {
+        match &change.change {
+            Change::MarketPrice {
+                price_list_id,
+                currency,
+                ..
+            } => {
+                if !price_lists.iter().any(|(id, _)| id == price_list_id) {
+                    price_lists.push((price_list_id, *currency));
+                }
+                continue;
+            }
+            Change::Inventory { location_id, .. }
+                if !location_ids.contains(&location_id.as_str()) =>
+            {
+                location_ids.push(location_id)
+            }
+            _ => {}
+        }
+        if !variant_ids.contains(&change.variant_id.as_str()) {
+            variant_ids.push(&change.variant_id);
+        }
+    }
+
+    let nodes: HashMap<String, Node> = if variant_ids.is_empty() {
+        HashMap::new()
+    } else {
+        fetch_variant_nodes(client, &variant_ids, &location_ids)
+            .await?
+            .into_iter()
+            .map(|n| (n.id.to_owned(), n))
+            .collect()
+    };
+    let mut fixed_prices = HashMap::new();
+    for (price_list_id, currency) in price_lists {
+        fixed_prices.insert(
+            price_list_id,
+            fetch_fixed_prices(client, price_list_id, currency).await?,
+        );
+    }
+
+    let mut live = Vec::new();
+    for change in changes {
+        let mut change = (*change).clone();
+        if let Change::MarketPrice {
+            price_list_id,
+            before,
+            ..
+        } = &mut change.change
+        {
+            *before = fixed_prices[price_list_id.as_str()]
+                .get(&change.variant_id)
+                .copied();
+            live.push(change);
+            continue;
+        }
+
+        let node = nodes
+            .get(&change.variant_id)
+            .ok_or(FixerError::Custom(format!(
+                "Variant {} no longer exists in Shopify",
+                &change.variant_id
+            )))?;
+        let cents = |price: &str| {
+            Money::parse(price, currency)
+                .map(|m| m.cents())
+                .map_err(|e| {
+                    FixerError::Custom(format!(
+                        "Could not parse the live price of variant {}: {}",
+                        &node.id, e
+                    ))
+                })
+        };
+        if let Some(sku) = &node.sku {
+            change.shopify_sku = sku.to_owned();
+        }
+        match &mut change.change {
+            Change::Price { before, .. } => *before = cents(&node.price)?,
+            Change::CompareAt { before, .. } => {
+                *before = node.compare_at_price.as_deref().map(cents).transpose()?
+            }
+            Change::Cost { before, .. } => {
+                *before = node.inventory_item.unit_cost.map(|c| c.cents())
+            }
+            Change::Inventory {
+                location_id,
+                before,
+                ..
+            } => {
+                let level = node
+                    .inventory_item
+                    .inventory_levels
+                    .values()
+                    .flatten()
+                    .find(|l| l.location.id == *location_id);
+                if let Some(quantity) = level.and_then(|l| l.quantities.first()) {
+                    *before = quantity.quantity;
+                }
+            }
+            Change::MarketPrice { .. } => {}
+        }
+        live.push(change);
+    }
+    Ok(live)
+}
+
+/// Record changes in the journal with the values Shopify holds right before they are sent, so
+/// undoing the run puts back what was really replaced. Changes whose values moved since the plan
+/// was built are logged to `warnings.txt`
+///
+/// # Arguments
+///
+/// * `client` - The `AdminClient` to read the live values with
+/// * `journal` - The `Journal` to record the changes in
+/// * `changes` - The changes about to be sent
+/// * `currency` - The currency of every price and cost in the plan
+/// * `log_to_stdout` - Passed to `log` for every change that moved
+///
+/// # Returns
+///
+/// `Err` with why the changes could not be recorded. They must not be sent in that case
+///
+/// # Errors
+///
+/// Will return `std::io::Error` if a log file could not be written
+async fn record_live(
+    client: &AdminClient,
+    journal: &Journal,
+    changes: &[&PlannedChange],
+    currency: Currency,
+    log_to_stdout: bool,
+) -> Result<Result<(), FixerError>, std::io::Error> {
+    let live = match live_changes(client, changes, currency).await {
+        Ok(l) => l,
+        Err(e) => return Ok(Err(e)),
+    };
+    for (planned, live) in changes.iter().zip(live.iter()) {
+        if planned.change != live.change {
+            log(
+                log_to_stdout,
+                Log::Warning,
+                format!("CHANGED SINCE PLAN {}, PLANNED AS {}", live, planned),
+            )?;
+        }
+    }
+    Ok(journal.record(&live.iter().collect::<Vec<_>>()))
+}
+
+/// Send every change in a plan to Shopify. Cost and inventory changes are sent per inventory item,
+/// price changes are sent in one bulk mutation per product, and market prices are sent per price
+/// list
+///
+/// # Arguments
+///
+/// * `client` - The `AdminClient` to send the mutations with
+/// * `plan` - The `Plan` to apply
+/// * `journal` - The `Journal` every change is recorded in before it is sent, with the values
+///   Shopify holds at that moment. Changes that cannot be recorded are not sent
+/// * `log_to_stdout` - Passed to `log` for every confirmed or failed change
+///
+/// # Returns
+///
+/// The `ApplyReport` of which variants were updated and which changes failed
+///
+/// # Errors
+///
+/// Will return `std::io::Error` if a log file could not be written
+pub async fn apply_plan(
+    client: &AdminClient,
+    plan: &Plan,
+    journal: &Journal,
+    log_to_stdout: bool,
+) -> Result<ApplyReport, std::io::Error> {
+    let mut report = ApplyReport::default();
+
+    // Group the changes so each inventory item and each product takes as few requests as
+    // possible, keeping the order of the plan
+    let mut inventory_items: Vec<&str> = Vec::new();
+    let mut inventory_changes: HashMap<&str, Vec<&PlannedChange>> = HashMap::new();
+    let mut products: Vec<&str> = Vec::new();
+    let mut price_changes: HashMap<&str, Vec<&PlannedChange>> = HashMap::new();
+    let mut price_lists: Vec<&str> = Vec::new();
+    let mut market_changes: HashMap<&str, Vec<&PlannedChange>> = HashMap::new();
+    for change in plan.changes.iter() {
+        let (order, groups, key) = match &change.change {
+            Change::Price { .. } | Change::CompareAt { .. } => {
+                (&mut products, &mut price_changes, &change.product_id)
+            }
+            Change::MarketPrice { price_list_id, .. } => {
+                (&mut price_lists, &mut market_changes, price_list_id)
+            }
+            _ => (
+                &mut inventory_items,
+                &mut inventory_changes,
+                &change.inventory_item_id,
+            ),
+        };
+        if !groups.contains_key(key.as_str()) {
+            order.push(key);
+        }
+        groups.entry(key).or_default().push(change);
+    }
+
+    for inventory_item_id in inventory_items {
+        let changes = &inventory_changes[inventory_item_id];
+        let cost = changes.iter().find_map(|c| match c.change {
+            Change::Cost { after, .. } => Some(Money::new(after, plan.currency)),
+            _ => None,
+        });
+        let quantities: Vec<LocationQuantity> = changes
+            .iter()
+            .filter_map(|c| match &c.change {
+                Change::Inventory {
+                    location_id, after, ..
+                } => Some(LocationQuantity {
+                    location_id: location_id.to_owned(),
+                    quantity: *after,
+                }),
+                _ => None,
+            })
+            .collect();
+
+        if let Err(e) = record_live(client, journal, changes, plan.currency, log_to_stdout).await? {
+            log(log_to_stdout, Log::Error, format!("ERROR {:?}", e))?;
+            for change in changes {
+                report.fail(change, &e);
+            }
+            continue;
+        }
+        match update_shopify_inventory(client, inventory_item_id, cost, &quantities).await {
+            Ok(_) => {
+                for change in changes {
+                    log(log_to_stdout, Log::Adjusted, format!("UPDATED {}", change))?;
+                    report.updated.insert(change.variant_id.to_owned());
+                }
+            }
+
+            Err(e) => {
+                log(
+                    log_to_stdout,
+                    Log::Error,
+                    format!(
+                        "ERROR updating inventory item with id {}: {:?}",
+                        inventory_item_id, e
+                    ),
+                )?;
+                for change in changes {
+                    report.fail(change, &e);
+                }
+            }
+        }
+    }
+
+    for product_id in products {
+        let changes = &price_changes[product_id];
+
+        // A variant can have both a price and a compare-at change, which go in one update
+        let mut prices: Vec<PriceUpdate> = Vec::new();
+        for c in changes.iter() {
+            let i = match prices.iter().position(|p| p.variant_id == c.variant_id) {
+                Some(i) => i,
+                None => {
+                    prices.push(PriceUpdate {
+                        variant_id: c.variant_id.to_owned(),
+                        sku: c.sku.to_owned(),
+                        price: None,
+                        compare_at_price: None,
+                    });
+                    prices.len() - 1
+                }
+            };
+            match c.change {
+                Change::Price { after, .. } => {
+                    prices[i].price = Some(Money::new(after, plan.currency))
+                }
+                Change::CompareAt { after, .. } => {
+                    prices[i].compare_at_price = Some(after.map(|a| Money::new(a, plan.currency)))
+                }
+                _ => {}
+            }
+        }
+
+        if let Err(e) = record_live(client, journal, changes, plan.currency, log_to_stdout).await? {
+            log(log_to_stdout, Log::Error, format!("ERROR {:?}", e))?;
+            for change in changes {
+                report.fail(change, &e);
+            }
+            continue;
+        }
+        match update_shopify_prices(client, product_id, &prices).await {
+            Ok(m) => {
+                for change in changes.iter() {
+                    // Only count the variant as updated if Shopify reports the new value back
+                    let variant = m
+                        .product_variants_bulk_update
+                        .product_variants
+                        .iter()
+                        .flatten()
+                        .find(|v| v.id == change.variant_id);
+                    let confirmed = variant.is_some_and(|v| match change.change {
+                        Change::Price { after, .. } => {
+                            Money::parse(&v.price, plan.currency)
+                                .ok()
+                                .map(|p| p.cents())
+                                == Some(after)
+                        }
+                        Change::CompareAt { after, .. } => {
+                            v.compare_at_price
+                                .as_ref()
+                                .and_then(|c| Money::parse(c, plan.currency).ok())
+                                .map(|c| c.cents())
+                                == after
+                        }
+                        _ => false,
+                    });
+                    if confirmed {
+                        log(log_to_stdout, Log::Adjusted, format!("UPDATED {}", change))?;
+                        report.updated.insert(change.variant_id.to_owned());
+                    } else {
+                        log(
+                            log_to_stdout,
+                            Log::Error,
+                            format!(
+                                "ERROR Shopify did not confirm the new {} in product {}",
+                                change, product_id
+                            ),
+                        )?;
+                        report
+                            .rejected
+                            .push(format!("{}: not confirmed by Shopify", change));
+                    }
+                }
+            }
+
+            Err(e) => {
+                log(
+                    log_to_stdout,
+                    Log::Error,
+                    format!(
+                        "ERROR updating prices of product with id {}: {:?}",
+                        product_id, e
+                    ),
+                )?;
+                for change in changes {
+                    report.fail(change, &e);
+                }
+            }
+        }
+    }
+
+    for price_list_id in price_lists {
+        let changes = &market_changes[price_list_id];
+        let mut to_add = Vec::new();
+        let mut to_delete = Vec::new();
+        for c in changes.iter() {
+            if let Change::MarketPrice {
+                currency, after, ..
+            } = c.change
+            {
+                match after {
+                    Some(a) => to_add.push((c.variant_id.to_owned(), Money::new(a, currency))),
+                    None => to_delete.push(c.variant_id.to_owned()),
+                }
+            }
+        }
+
+        if let Err(e) = record_live(client, journal, changes, plan.currency, log_to_stdout).await? {
+            log(log_to_stdout, Log::Error, format!("ERROR {:?}", e))?;
+            for change in changes {
+                report.fail(change, &e);
+            }
+            continue;
+        }
+        match update_fixed_prices(client, price_list_id, &to_add, &to_delete).await {
+            Ok(responses) => {
+                let updates = responses.iter().map(|r| &r.price_list_fixed_prices_update);
+                let added: Vec<_> = updates
+                    .clone()
+                    .flat_map(|u| u.prices_added.iter().flatten())
+                    .collect();
+                let deleted: Vec<_> = updates
+                    .flat_map(|u| u.deleted_fixed_price_variant_ids.iter().flatten())
+                    .collect();
+                for change in changes.iter() {
+                    // Only count the variant as updated if Shopify reports the new value back
+                    let confirmed = match change.change {
+                        Change::MarketPrice {
+                            after: Some(after), ..
+                        } => added
+                            .iter()
+                            .any(|p| p.variant.id == change.variant_id && p.price.cents() == after),
+                        _ => deleted.iter().any(|id| **id == change.variant_id),
+                    };
+                    if confirmed {
+                        log(log_to_stdout, Log::Adjusted, format!("UPDATED {}", change))?;
+                        report.updated.insert(change.variant_id.to_owned());
+                    } else {
+                        log(
+                            log_to_stdout,
+                            Log::Error,
+                            format!(
+                                "ERROR Shopify did not confirm the new {} in price list {}",
+                                change, price_list_id
+                            ),
+                        )?;
+                        report
+                            .rejected
+                            .push(format!("{}: not confirmed by Shopify", change));
+                    }
+                }
+            }
+
+            Err(e) => {
+                log(
+                    log_to_stdout,
+                    Log::Error,
+                    format!(
+                        "ERROR updating fixed prices of price list with id {}: {:?}",
+                        price_list_id, e
+                    ),
+                )?;
+                for change in changes {
+                    report.fail(change, &e);
+                }
+            }
+        }
+    }
+
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::product::AbcProductBuilder;
+    use crate::rules::DEFAULT_RULE;
+    use crate::upc::Upc;
+
+    fn usd(cents: i64) -> Money {
+        Money::new(cents, Currency::USD)
+    }
+
+    fn config() -> Config {
+        serde_json::from_str(
+            r#"{
+                "shopify_access_token": "x",
+                "business_url": "example.myshopify.com",
+                "storefront_url": "example.com",
+                "api_version": "2026-07",
+                "locations": [
+                    { "id": "gid://shopify/Location/1" },
+                    { "id": "gid://shopify/Location/2" }
+                ]
+            }"#,
+        )
+        .unwrap()
+    }
+
+    fn shopify(sku: &str, price: i64, barcode: Option<&str>) -> ShopifyProduct {
+        ShopifyProduct {
+            id: format!("gid://shopify/ProductVariant/{}", sku),
+            sku: sku.to_string(),
+            display_name: "Item".to_string(),
+            price: usd(price),
+            compare_at_price: None,
+            barcode: barcode.map(|b| Upc::try_from(b).unwrap()),
+            available_for_sale: true,
+            inventory_item_id: format!("gid://shopify/InventoryItem/{}", sku),
+            cost: Some(usd(600)),
+            stock: HashMap::from([("gid://shopify/Location/1".to_string(), 5)]),
+            market_prices: HashMap::new(),
+            product_id: "gid://shopify/Product/1".to_string(),
+            is_active: true,
+            vendor: String::new(),
+            product_type: String::new(),
+            tags: Vec::new(),
+        }
+    }
+
+    fn abc_products(products: Vec<AbcProduct>) -> HashMap<String, AbcProduct> {
+        products.into_iter().map(|p| (p.sku(), p)).collect()
+    }
+
+    fn abc(sku: &str, list: i64, stock: f64) -> AbcProductBuilder {
+        AbcProductBuilder::new()
+            .with_sku(sku)
+            .with_desc("Item")
+            .with_list(usd(list))
+            .with_cost(usd(600))
+            .with_stock(stock)
+    }
+
+    fn build(
+        abc_products: &HashMap<String, AbcProduct>,
+        shopify_products: &[ShopifyProduct],
+    ) -> Plan {
+        build_plan(
+            &config(),
+            &PricingRules::default(),
+            &Promotions::default(),
+            &MapList::default(),
+            abc_products,
+            shopify_products,
+            true,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn plans_price_cost_and_stock_at_stocked_locations() {
+        let abc_products = abc_products(vec![abc("A1", 1299, 3.0)
+            .with_cost(usd(650))
+            .build()
+            .unwrap()]);
+        let plan = build(&abc_products, &[shopify("A1", 1000, None)]);
+
+        let changes: Vec<&Change> = plan.changes.iter().map(|c| &c.change).collect();
+        assert_eq!(
+            changes,
+            [
+                &Change::Price {
+                    before: 1000,
+                    after: 1299
+                },
+                &Change::Cost {
+                    before: Some(600),
+                    after: 650
+                },
+                &Change::Inventory {
+                    location_id: "gid://shopify/Location/1".to_string(),
+                    before: 5,
+                    after: 3
+                },
+            ]
+        );
+        assert_eq!(plan.changes[0].rule.as_deref(), Some(DEFAULT_RULE));
+    }
+
+    #[test]
+    fn plans_nothing_for_variants_that_match_abc() {
+        let abc_products = abc_products(vec![abc("A1", 1000, 5.0).build().unwrap()]);
+        let mut inactive = shopify("A1", 900, None);
+        inactive.is_active = false;
+        let plan = build(
+            &abc_products,
+            &[
+                shopify("A1", 1000, None),
+                inactive,
+                shopify("ZZ", 100, None),
+            ],
+        );
+        assert!(plan.changes.is_empty());
+        assert_eq!(plan.variants_matched, 1);
+    }
+
+    #[test]
+    fn variant_matched_by_upc_gets_the_abc_sku() {
+        let upc = "012345678905";
+        let abc_products = abc_products(vec![abc("A1", 1000, 5.0)
+            .add_upc(Upc::try_from(upc).unwrap())
+            .build()
+            .unwrap()]);
+        let plan = build(&abc_products, &[shopify("OLD", 1000, Some(upc))]);
+
+        assert_eq!(plan.changes.len(), 1);
+        assert_eq!(plan.changes[0].sku, "A1");
+        assert_eq!(plan.changes[0].shopify_sku, "OLD");
+        assert_eq!(
+            plan.changes[0].change,
+            Change::Price {
+                before: 1000,
+                after: 1000
+            }
+        );
+    }
+}
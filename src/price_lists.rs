@@ -0,0 +1,383 @@
+use crate::admin::AdminClient;
+use crate::money::{Currency, Money};
+use crate::product::{check_user_errors, ShopifyProduct, UserError};
+use crate::rounding::Rounding;
+use crate::{Config, FixerError};
+use serde::Deserialize;
+use std::collections::HashMap;
+
+/// The most fixed prices Shopify accepts in one price list mutation
+const MAX_PRICES_PER_UPDATE: usize = 250;
+
+fn default_rate() -> f64 {
+    1.0
+}
+
+/// A Shopify Markets price list that the fixer keeps fixed prices in, converted from the price the
+/// rules decide
+#[derive(Debug, Clone, Deserialize)]
+pub struct PriceList {
+    /// The Shopify ID of the price list. Like "gid://shopify/PriceList/1234567890"
+    pub id: String,
+
+    /// The currency of the price list. Like "CAD"
+    pub currency: Currency,
+
+    /// Optional. How many units of `currency` one unit of the store currency is worth. Must be
+    /// above 0. If left blank, assume 1
+    #[serde(default = "default_rate")]
+    pub rate: f64,
+
+    /// Optional. The percent added on top of the converted price. If left blank, assume 0
+    #[serde(default)]
+    pub markup_percent: f64,
+
+    /// Optional. How the converted price is rounded. If left blank, it is not rounded
+    #[serde(default)]
+    pub rounding: Option<Rounding>,
+}
+
+impl PriceList {
+    /// Work out the fixed price of a variant in this price list
+    ///
+    /// # Arguments
+    ///
+    /// * `price` - The price of the variant in the store currency
+    ///
+    /// # Returns
+    ///
+    /// The price in `currency`
+    pub fn price(&self, price: Money) -> Money {
+        // In millionths of the rate and hundredths of a percent, since a float like 1000 * 1.1
+        // lands just above 1100 and could round the wrong way on a half cent
+        let rate = (self.rate * 1_000_000.0).round() as i128;
+        let markup = (self.markup_percent * 100.0).round() as i128;
+        let scaled = (price.cents() as i128)
+            .saturating_mul(rate)
+            .saturating_mul(10_000 + markup);
+        let divisor = 10_000_000_000;
+        // Half a cent rounds away from 0, like `f64::round`
+        let cents = (scaled + scaled.signum() * divisor / 2) / divisor;
+        let price = Money::new(
+            cents.clamp(i64::MIN as i128, i64::MAX as i128) as i64,
+            self.currency,
+        );
+        self.rounding.as_ref().map_or(price, |r| r.round(price))
+    }
+}
+
+impl std::fmt::Display for PriceList {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "price list {} in {} at rate {} with markup {}%",
+            &self.id, self.currency, self.rate, self.markup_percent
+        )?;
+        if let Some(rounding) = &self.rounding {
+            write!(f, ", {}", rounding)?;
+        }
+        Ok(())
+    }
+}
+
+/// The fixed prices already set in each configured price list
+#[derive(Debug, Clone, Default)]
+pub struct FixedPrices {
+    /// Fixed price in cents keyed by price list ID, then by variant ID
+    by_list: HashMap<String, HashMap<String, i64>>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct PriceListData {
+    pub price_list: Option<PriceListNode>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct PriceListNode {
+    pub prices: PriceListPrices,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct PriceListPrices {
+    pub nodes: Vec<PriceListPrice>,
+    pub page_info: PriceListPageInfo,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct PriceListPageInfo {
+    pub has_next_page: bool,
+    pub end_cursor: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct PriceListPrice {
+    pub variant: PriceListVariant,
+    pub price: Money,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct PriceListVariant {
+    pub id: String,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct PriceListFixedPricesUpdateData {
+    pub price_list_fixed_prices_update: PriceListFixedPricesUpdate,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct PriceListFixedPricesUpdate {
+    pub prices_added: Option<Vec<PriceListPrice>>,
+    pub deleted_fixed_price_variant_ids: Option<Vec<String>>,
+    pub user_errors: Vec<UserError>,
+}
+
+impl FixedPrices {
+    /// Fetch the fixed prices of every price list in the config
+    ///
+    /// # Arguments
+    ///
+    /// * `client` - The `AdminClient` to send the queries with
+    /// * `config` - The `Config` holding the price lists
+    ///
+    /// # Errors
+    ///
+    /// * Will return `FixerError::Custom` if a price list does not exist or is in another currency
+    ///   than the config says
+    /// * Will return a `FixerError` if sending a request fails or a response cannot be parsed
+    pub async fn fetch(client: &AdminClient, config: &Config) -> Result<Self, FixerError> {
+        let mut fixed_prices = FixedPrices::default();
+        for price_list in config.price_lists.iter() {
+            let prices = fetch_fixed_prices(client, &price_list.id, price_list.currency).await?;
+            fixed_prices
+                .by_list
+                .insert(price_list.id.to_owned(), prices);
+        }
+        Ok(fixed_prices)
+    }
+
+    /// Set the `market_prices` of every Shopify variant to its fixed prices
+    ///
+    /// # Arguments
+    ///
+    /// * `shopify_products` - The variants fetched from Shopify
+    pub fn fill(&self, shopify_products: &mut [ShopifyProduct]) {
+        for shopify_product in shopify_products.iter_mut() {
+            shopify_product.market_prices = self
+                .by_list
+                .iter()
+                .filter_map(|(list_id, prices)| {
+                    let price = prices.get(&shopify_product.id)?;
+                    Some((list_id.to_owned(), *price))
+                })
+                .collect();
+        }
+    }
+}
+
+/// Fetch the fixed prices of one price list
+///
+/// # Arguments
+///
+/// * `client` - The `AdminClient` to send the queries with
+/// * `price_list_id` - The Shopify ID of the price list
+/// * `currency` - The currency the price list should be in
+///
+/// # Returns
+///
+/// The fixed price in cents keyed by variant ID
+///
+/// # Errors
+///
+/// * Will return `FixerError::Custom` if the price list does not exist or is in another currency
+/// * Will return a `FixerError` if sending a request fails or a response cannot be parsed
+pub async fn fetch_fixed_prices(
+    client: &AdminClient,
+    price_list_id: &str,
+    currency: Currency,
+) -> Result<HashMap<String, i64>, FixerError> {
+    let document = format!(
+        r#"
+        query ($id: ID!, $cursor: String) {{
+            priceList(id: $id) {{
+                prices(first: {}, after: $cursor, originType: FIXED) {{
+                    nodes {{
+                        variant {{
+                            id
+                        }}
+                        price {{
+                            amount
+                            currencyCode
+                        }}
+                    }}
+                    pageInfo {{
+                        hasNextPage
+                        endCursor
+                    }}
+                }}
+            }}
+        }}"#,
+        MAX_PRICES_PER_UPDATE
+    );
+
+    let mut prices = HashMap::new();
+    let mut cursor: Option<String> = None;
+    loop {
+        let query = serde_json::json!({
+            "query": document,
+            "variables": {
+                "id": price_list_id,
+                "cursor": cursor,
+            }
+        });
+        let data: PriceListData = client.graphql(&query).await?;
+        let page = data
+            .price_list
+            .ok_or(FixerError::Custom(format!(
+                "Price list {} does not exist",
+                price_list_id
+            )))?
+            .prices;
+        for node in page.nodes {
+            if node.price.currency() != currency {
+                return Err(FixerError::Custom(format!(
+                    "Price list {} is in {}, not {}",
+                    price_list_id,
+                    node.price.currency(),
+                    currency
+                )));
+            }
+            prices.insert(node.variant.id, node.price.cents());
+        }
+        match page.page_info.end_cursor {
+            Some(c) if page.page_info.has_next_page => cursor = Some(c),
+            _ => break,
+        }
+    }
+    Ok(prices)
+}
+
+/// Send `priceListFixedPricesUpdate` mutations to set and remove fixed prices in one price list,
+/// at most `MAX_PRICES_PER_UPDATE` at a time
+///
+/// # Arguments
+///
+/// * `client` - The `AdminClient` to send the mutations with
+/// * `price_list_id` - The Shopify ID of the price list
+/// * `to_add` - The fixed prices to set, by variant ID
+/// * `to_delete` - The IDs of the variants whose fixed price is removed
+///
+/// # Returns
+///
+/// The response of every mutation sent
+///
+/// # Errors
+///
+/// * Will return `FixerError::UserErrors` if Shopify rejected a change
+/// * Will return a `FixerError` if sending a request fails or a response cannot be parsed
+pub async fn update_fixed_prices(
+    client: &AdminClient,
+    price_list_id: &str,
+    to_add: &[(String, Money)],
+    to_delete: &[String],
+) -> Result<Vec<PriceListFixedPricesUpdateData>, FixerError> {
+    let mut responses = Vec::new();
+    let mut add_chunks = to_add.chunks(MAX_PRICES_PER_UPDATE);
+    let mut delete_chunks = to_delete.chunks(MAX_PRICES_PER_UPDATE);
+    loop {
+        let (add, delete) = match (add_chunks.next(), delete_chunks.next()) {
+            (None, None) => break,
+            (a, d) => (a.unwrap_or_default(), d.unwrap_or_default()),
+        };
+        let prices: Vec<serde_json::Value> = add
+            .iter()
+            .map(|(variant_id, price)| {
+                serde_json::json!({
+                    "variantId": variant_id,
+                    "price": price,
+                })
+            })
+            .collect();
+        let query = serde_json::json!({
+            "query": r#"
+                mutation priceListFixedPricesUpdate($priceListId: ID!, $pricesToAdd: [PriceListPriceInput!]!, $variantIdsToDelete: [ID!]!) {
+                    priceListFixedPricesUpdate(priceListId: $priceListId, pricesToAdd: $pricesToAdd, variantIdsToDelete: $variantIdsToDelete) {
+                        pricesAdded {
+                            variant {
+                                id
+                            }
+                            price {
+                                amount
+                                currencyCode
+                            }
+                        }
+                        deletedFixedPriceVariantIds
+                        userErrors {
+                            field
+                            message
+                        }
+                    }
+                }"#,
+            "variables": {
+                "priceListId": price_list_id,
+                "pricesToAdd": prices,
+                "variantIdsToDelete": delete,
+            }
+        });
+        let data: PriceListFixedPricesUpdateData = client.graphql(&query).await?;
+        check_user_errors(&data.price_list_fixed_prices_update.user_errors)?;
+        responses.push(data);
+    }
+    Ok(responses)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn price_list(rate: f64, markup_percent: f64, rounding: Option<Rounding>) -> PriceList {
+        PriceList {
+            id: "gid://shopify/PriceList/1".to_string(),
+            currency: "CAD".parse().unwrap(),
+            rate,
+            markup_percent,
+            rounding,
+        }
+    }
+
+    #[test]
+    fn price_is_converted_into_the_list_currency() {
+        let cad = "CAD".parse().unwrap();
+        let price = price_list(1.36, 5.0, None).price(Money::new(1000, Currency::USD));
+        assert_eq!(price, Money::new(1428, cad));
+    }
+
+    #[test]
+    fn price_is_converted_without_float_error() {
+        let cad = "CAD".parse().unwrap();
+        // 1.1 * 1000 is just above 1100 as a float
+        let price = price_list(1.1, 0.0, None).price(Money::new(1000, Currency::USD));
+        assert_eq!(price, Money::new(1100, cad));
+        // Half a cent rounds up
+        let price = price_list(1.5, 0.0, None).price(Money::new(1, Currency::USD));
+        assert_eq!(price, Money::new(2, cad));
+    }
+
+    #[test]
+    fn converted_price_is_rounded() {
+        let cad = "CAD".parse().unwrap();
+        let list = price_list(1.36, 0.0, Some(Rounding::NinetyNine));
+        assert_eq!(
+            list.price(Money::new(1000, Currency::USD)),
+            Money::new(1399, cad)
+        );
+    }
+}
@@ -1,12 +1,18 @@
-use crate::{create_client_with_headers, upc::Upc, Config, FixerError};
+use crate::{
+    abc_columns::{AbcColumns, OrphanPolicy, Reject, Rejects, Schema, Unmatched},
+    admin::AdminClient,
+    money::{Currency, Money},
+    upc::Upc,
+    Config, FixerError,
+};
 use serde::{ser::Error, Deserialize};
-use std::{collections::HashMap, num::ParseFloatError};
+use std::{
+    collections::{HashMap, HashSet},
+    time::Duration,
+};
 
-#[derive(Deserialize, Debug)]
-#[serde(rename_all = "camelCase")]
-pub struct UpdateShopifyPriceResponse {
-    pub data: UpdateShopifyPriceData,
-}
+/// How long to wait between checks on a running bulk operation
+const BULK_POLL_INTERVAL: Duration = Duration::from_secs(2);
 
 #[derive(Deserialize, Debug)]
 #[serde(rename_all = "camelCase")]
@@ -17,8 +23,9 @@ pub struct UpdateShopifyPriceData {
 #[derive(Deserialize, Debug)]
 #[serde(rename_all = "camelCase")]
 pub struct ProductVariantsBulkUpdate {
-    pub product: Product,
-    pub product_variants: Vec<UpdateShopifyPriceProductVariant>,
+    pub product: Option<Product>,
+    pub product_variants: Option<Vec<UpdateShopifyPriceProductVariant>>,
+    pub user_errors: Vec<UserError>,
 }
 
 #[derive(Deserialize, Debug)]
@@ -27,12 +34,141 @@ pub struct UpdateShopifyPriceProductVariant {
     pub id: String,
     pub sku: String,
     pub price: String,
+    #[serde(default)]
+    pub compare_at_price: Option<String>,
+}
+
+/// A reason Shopify gave for rejecting a mutation
+#[derive(Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct UserError {
+    /// The path to the input field that caused the error, if any
+    pub field: Option<Vec<String>>,
+    pub message: String,
+}
+
+impl std::fmt::Display for UserError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.field {
+            Some(field) => write!(f, "{}: {}", field.join("."), self.message),
+            None => write!(f, "{}", self.message),
+        }
+    }
+}
+
+/// Turn the `userErrors` of a mutation into an error if there are any
+///
+/// # Arguments
+///
+/// * `user_errors` - The `userErrors` returned by a mutation
+///
+/// # Errors
+///
+/// Will return `FixerError::UserErrors` if `user_errors` is not empty
+pub fn check_user_errors(user_errors: &[UserError]) -> Result<(), FixerError> {
+    if user_errors.is_empty() {
+        Ok(())
+    } else {
+        Err(FixerError::UserErrors(user_errors.to_vec()))
+    }
 }
 
 #[derive(Deserialize, Debug)]
 #[serde(rename_all = "camelCase")]
-pub struct FetchShopifyProductsResponse {
-    pub data: Data,
+pub struct InventoryItemUpdateData {
+    pub inventory_item_update: InventoryItemUpdate,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct InventoryItemUpdate {
+    pub inventory_item: Option<UpdatedInventoryItem>,
+    pub user_errors: Vec<UserError>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct UpdatedInventoryItem {
+    pub id: String,
+    pub tracked: bool,
+    pub unit_cost: Option<Money>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct InventorySetQuantitiesData {
+    pub inventory_set_quantities: InventorySetQuantities,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct InventorySetQuantities {
+    pub inventory_adjustment_group: Option<InventoryAdjustmentGroup>,
+    pub user_errors: Vec<UserError>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct InventoryAdjustmentGroup {
+    pub created_at: String,
+    pub changes: Vec<InventoryChange>,
+    pub reason: String,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct InventoryChange {
+    pub item: InventoryChangeItem,
+    pub quantity_after_change: Option<i64>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct InventoryChangeItem {
+    pub sku: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct BulkOperationRunQueryData {
+    pub bulk_operation_run_query: BulkOperationRunQuery,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct BulkOperationRunQuery {
+    pub bulk_operation: Option<BulkOperation>,
+    pub user_errors: Vec<UserError>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct BulkOperationCancelData {
+    pub bulk_operation_cancel: BulkOperationCancel,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct BulkOperationCancel {
+    pub user_errors: Vec<UserError>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct BulkOperationNodeData {
+    pub node: Option<BulkOperation>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct BulkOperation {
+    pub id: String,
+    pub status: String,
+    pub error_code: Option<String>,
+    pub object_count: Option<String>,
+
+    /// Where to download the JSONL result from once the operation is completed
+    pub url: Option<String>,
 }
 
 #[derive(Deserialize, Debug)]
@@ -54,6 +190,13 @@ pub struct Edge {
     pub node: Node,
 }
 
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct NodesData {
+    /// `None` for every ID that no longer exists
+    pub nodes: Vec<Option<Node>>,
+}
+
 #[derive(Deserialize, Debug, Clone)]
 #[serde(rename_all = "camelCase")]
 pub struct Node {
@@ -61,6 +204,7 @@ pub struct Node {
     pub sku: Option<String>,
     pub display_name: String,
     pub price: String,
+    pub compare_at_price: Option<String>,
     pub barcode: Option<String>,
     pub available_for_sale: bool,
     pub inventory_item: InventoryItem,
@@ -72,15 +216,27 @@ pub struct Node {
 pub struct InventoryItem {
     pub id: String,
     pub tracked: bool,
-    pub inventory_level: InventoryLevel,
+    pub unit_cost: Option<Money>,
+
+    /// One aliased `inventoryLevel` per configured location. A level is `None` when the item is
+    /// not stocked at that location
+    #[serde(flatten)]
+    pub inventory_levels: HashMap<String, Option<InventoryLevel>>,
 }
 
 #[derive(Deserialize, Debug, Clone)]
 #[serde(rename_all = "camelCase")]
 pub struct InventoryLevel {
+    pub location: Location,
     pub quantities: Vec<Quantity>,
 }
 
+#[derive(Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct Location {
+    pub id: String,
+}
+
 #[derive(Deserialize, Debug, Clone)]
 #[serde(rename_all = "camelCase")]
 pub struct Quantity {
@@ -92,6 +248,14 @@ pub struct Quantity {
 pub struct Product {
     pub id: String,
     pub status: String,
+
+    /// Only queried when fetching variants, not by mutations
+    #[serde(default)]
+    pub vendor: String,
+    #[serde(default)]
+    pub product_type: String,
+    #[serde(default)]
+    pub tags: Vec<String>,
 }
 
 #[derive(Deserialize, Debug)]
@@ -105,17 +269,20 @@ pub struct PageInfo {
 #[derive(Deserialize, Debug)]
 #[serde(rename_all = "camelCase")]
 pub struct Cost {
-    pub requested_query_cost: u32,
-    pub actual_query_cost: u32,
+    pub requested_query_cost: f64,
+
+    /// `None` when the request was THROTTLED, since it never ran
+    pub actual_query_cost: Option<f64>,
     pub throttle_status: ThrottleStatus,
 }
 
+/// The state of the leaky bucket. Shopify reports every field as a float, like `2000.0`
 #[derive(Deserialize, Debug)]
 #[serde(rename_all = "camelCase")]
 pub struct ThrottleStatus {
-    pub maximum_available: u32,
-    pub currently_available: u32,
-    pub restore_rate: u32,
+    pub maximum_available: f64,
+    pub currently_available: f64,
+    pub restore_rate: f64,
 }
 
 #[derive(Debug)]
@@ -123,27 +290,65 @@ pub struct ShopifyProduct {
     pub id: String,
     pub sku: String,
     pub display_name: String,
-    pub price: i64,
+    pub price: Money,
+
+    /// The compare-at price, if it has one. The variant is on sale when this is above `price`
+    pub compare_at_price: Option<Money>,
     pub barcode: Option<Upc>,
     pub available_for_sale: bool,
     pub inventory_item_id: String,
-    pub stock: i64,
+
+    /// The unit cost of the inventory item, if it has one
+    pub cost: Option<Money>,
+
+    /// On hand quantity keyed by location ID, for each configured location the item is stocked at
+    pub stock: HashMap<String, i64>,
+
+    /// Fixed price in cents keyed by price list ID, for each configured price list the variant
+    /// has one in. Filled in by `FixedPrices::fill`
+    pub market_prices: HashMap<String, i64>,
     pub product_id: String,
     pub is_active: bool,
-}
 
-impl TryFrom<Node> for ShopifyProduct {
-    type Error = FixerError;
+    /// The vendor, product type and tags of the product that owns the variant, for matching
+    /// pricing rules
+    pub vendor: String,
+    pub product_type: String,
+    pub tags: Vec<String>,
+}
 
-    fn try_from(value: Node) -> Result<Self, Self::Error> {
+impl ShopifyProduct {
+    /// Parse a fetched variant
+    ///
+    /// # Arguments
+    ///
+    /// * `value` - The fetched `Node`
+    /// * `currency` - The currency of the store. Shopify gives variant prices without one
+    ///
+    /// # Errors
+    ///
+    /// Will return `FixerError::Custom` if the node has no SKU, no inventory at any configured
+    /// location, or a price that is not an exact amount of money
+    pub fn from_node(value: Node, currency: Currency) -> Result<Self, FixerError> {
         let barcode = match value.barcode {
             Some(b) => Upc::try_from(b).ok(),
             None => None,
         };
-        let price = price_from_str(&value.price).or(Err(FixerError::Custom(format!(
-            "Could not parse float from {} for Node with id {}",
-            &value.price, &value.id
-        ))))?;
+        let price = Money::parse(&value.price, currency).map_err(|e| {
+            FixerError::Custom(format!(
+                "Could not parse price for Node with id {}: {}",
+                &value.id, e
+            ))
+        })?;
+        let compare_at_price = match &value.compare_at_price {
+            Some(c) => Some(Money::parse(c, currency).map_err(|e| {
+                FixerError::Custom(format!(
+                    "Could not parse compare-at price for Node with id {}: {}",
+                    &value.id, e
+                ))
+            })?),
+            None => None,
+        };
         let sku = &value
             .sku
             .ok_or(FixerError::Custom(format!(
@@ -151,236 +356,935 @@ impl TryFrom<Node> for ShopifyProduct {
                 &value.id,
             )))?
             .to_uppercase();
-        let stock = &value
-            .inventory_item
-            .inventory_level
-            .quantities
-            .get(0)
-            .ok_or(FixerError::Custom(format!(
+        let cost = value.inventory_item.unit_cost;
+        let mut stock = HashMap::new();
+        for level in value.inventory_item.inventory_levels.values().flatten() {
+            let quantity = level
+                .quantities
+                .first()
+                .ok_or(FixerError::Custom(format!(
+                    "Missing inventory on_hand at {} for Node with id {}",
+                    &level.location.id, &value.id
+                )))?
+                .quantity;
+            stock.insert(level.location.id.to_owned(), quantity);
+        }
+        if stock.is_empty() {
+            return Err(FixerError::Custom(format!(
                 "Missing inventory on_hand for Node with id {}",
                 &value.id
-            )))?
-            .quantity;
+            )));
+        }
         Ok(Self {
             id: value.id,
             sku: sku.to_owned(),
             display_name: value.display_name,
             price,
+            compare_at_price,
             barcode,
             available_for_sale: value.available_for_sale,
             product_id: value.product.id,
             inventory_item_id: value.inventory_item.id,
-            stock: stock.to_owned(),
+            cost,
+            stock,
+            market_prices: HashMap::new(),
             is_active: value.product.status == "ACTIVE",
+            vendor: value.product.vendor,
+            product_type: value.product.product_type,
+            tags: value.product.tags,
+        })
+    }
+}
+
+/// Build the fields queried for every product variant, shared by the paged, bulk and by ID fetches
+///
+/// # Arguments
+///
+/// * `location_ids` - The IDs of the locations to query inventory levels for
+///
+/// # Returns
+///
+/// The selection set of a `ProductVariant` node, including the surrounding braces
+fn variant_node_fields(location_ids: &[&str]) -> String {
+    let inventory_levels = location_ids
+        .iter()
+        .enumerate()
+        .map(|(i, location_id)| {
+            format!(
+                r#"
+                        location{}: inventoryLevel(locationId: "{}") {{
+                            location {{
+                                id
+                            }}
+                            quantities(names: ["on_hand"]) {{
+                                quantity
+                            }}
+                        }}"#,
+                i, location_id
+            )
         })
+        .collect::<String>();
+
+    format!(
+        r#"{{
+                    id
+                    sku
+                    displayName
+                    price
+                    compareAtPrice
+                    barcode
+                    availableForSale
+                    inventoryItem {{
+                        id
+                        tracked
+                        unitCost {{
+                            amount
+                            currencyCode
+                        }} {}
+                    }}
+                    product {{
+                        id
+                        status
+                        vendor
+                        productType
+                        tags
+                    }}
+                }}"#,
+        inventory_levels
+    )
+}
+
+/// Sort fetched nodes into the `ShopifyProduct`s that could be parsed and the `Node`s that could
+/// not
+fn push_node(
+    node: Node,
+    currency: Currency,
+    products: &mut Vec<ShopifyProduct>,
+    failed_nodes: &mut Vec<Node>,
+) {
+    match ShopifyProduct::from_node(node.clone(), currency) {
+        Ok(p) => products.push(p),
+        Err(_) => failed_nodes.push(node),
+    }
+}
+
+/// The IDs of every configured location, in order
+fn location_ids(config: &Config) -> Vec<&str> {
+    config.locations.iter().map(|l| l.id.as_str()).collect()
+}
+
+/// Fetch product variants by their IDs, as Shopify holds them right now
+///
+/// # Arguments
+///
+/// * `client` - The `AdminClient` to send the queries with
+/// * `ids` - The Shopify IDs of the variants
+/// * `location_ids` - The IDs of the locations to query inventory levels for
+///
+/// # Returns
+///
+/// The `Node` of every variant that still exists
+///
+/// # Errors
+///
+/// Will return a `FixerError` if sending a request fails or a response cannot be parsed
+pub async fn fetch_variant_nodes(
+    client: &AdminClient,
+    ids: &[&str],
+    location_ids: &[&str],
+) -> Result<Vec<Node>, FixerError> {
+    let document = format!(
+        r#"
+        query ($ids: [ID!]!) {{
+            nodes(ids: $ids) {{
+                ... on ProductVariant {}
+            }}
+        }}"#,
+        variant_node_fields(location_ids)
+    );
+
+    let mut nodes = Vec::new();
+    for ids in ids.chunks(250) {
+        let query = serde_json::json!({
+            "query": document,
+            "variables": {
+                "ids": ids,
+            }
+        });
+        let data: NodesData = client.graphql(&query).await?;
+        nodes.extend(data.nodes.into_iter().flatten());
     }
+    Ok(nodes)
 }
 
 pub async fn fetch_shopify_products(
+    client: &AdminClient,
     config: &Config,
 ) -> Result<(Vec<ShopifyProduct>, Vec<Node>), FixerError> {
-    let (client, headers) =
-        create_client_with_headers(config, "application/json").or(Err(FixerError::Custom(
-            "Encountered InvalidHeaderValue when building client to fetch shopify products"
-                .to_string(),
-        )))?;
     let mut failed_nodes = Vec::new();
     let mut products = Vec::new();
     let mut has_next_page = true;
     let mut cursor = None;
 
+    // Each location adds an aliased inventoryLevel to every variant, so shrink the page to keep
+    // the cost of a page close to that of a single location. With more than 250 locations a page
+    // still holds one variant
+    let page_size = (250 / config.locations.len().max(1)).max(1);
+
+    // Keep the document the same for every page so the throttle can reuse its cost
+    let document = format!(
+        r#"
+        query ($cursor: String) {{
+            productVariants(first: {}, after: $cursor) {{
+                edges {{
+                    node {}
+                }}
+                pageInfo {{
+                    hasNextPage
+                    endCursor
+                    startCursor
+                }}
+            }}
+        }}"#,
+        page_size,
+        variant_node_fields(&location_ids(config))
+    );
+
     while has_next_page {
         let query = serde_json::json!({
-            "query": format!(
-                r#"
-                {{
-                    productVariants(first: 250{}) {{
-                        edges {{
-                            node {{
-                                id
-                                sku
-                                displayName
-                                price
-                                barcode
-                                availableForSale
-                                inventoryItem {{
-                                    id
-                                    tracked 
-                                    inventoryLevel(locationId: "gid://shopify/Location/5535957028") {{
-                                        quantities(names: ["on_hand"]) {{
-                                            quantity
-                                        }}
-                                    }}
-                                }}
-                                product {{
-                                    id 
-                                    status
-                                }}
-                            }}
-                        }}
-                        pageInfo {{
-                            hasNextPage
-                            endCursor
-                            startCursor
-                        }}
-                    }}
-                }}"#,
-                cursor.map_or("".to_string(), |c| format!(" after: \"{}\"", c))
-            )
+            "query": document,
+            "variables": {
+                "cursor": cursor,
+            }
         });
 
-        let url = format!(
-            "https://{}/admin/api/2024-10/graphql.json",
-            config.business_url
-        );
-
-        let response = client
-            .post(&url)
-            .headers(headers.to_owned())
-            .body(query.to_string())
-            .send()
-            .await?;
-
-        let text = response.text().await?;
-        let graphql: FetchShopifyProductsResponse = serde_json::from_str(&text)?;
-        has_next_page = graphql.data.product_variants.page_info.has_next_page;
-        cursor = Some(graphql.data.product_variants.page_info.end_cursor);
-
-        for edge in graphql.data.product_variants.edges {
-            match ShopifyProduct::try_from(edge.node.clone()) {
-                Ok(p) => products.push(p),
-                Err(_) => failed_nodes.push(edge.node),
-            }
+        let data: Data = client.graphql(&query).await?;
+        has_next_page = data.product_variants.page_info.has_next_page;
+        cursor = Some(data.product_variants.page_info.end_cursor);
+
+        for edge in data.product_variants.edges {
+            push_node(edge.node, config.currency, &mut products, &mut failed_nodes);
+        }
+    }
+
+    Ok((products, failed_nodes))
+}
+
+/// Fetch every product variant with a Shopify Bulk Operation instead of paging through them. The
+/// operation runs on Shopify's side and its result is downloaded as one JSONL file, which is much
+/// faster for stores with tens of thousands of variants
+///
+/// # Arguments
+///
+/// * `client` - The `AdminClient` to run the operation with
+/// * `config` - The `Config` holding the locations to query inventory levels for
+///
+/// # Returns
+///
+/// The same `(Vec<ShopifyProduct>, Vec<Node>)` pair as `fetch_shopify_products`
+///
+/// # Errors
+///
+/// * Will return `FixerError::UserErrors` if Shopify refuses to start the operation. Such as when
+///   another bulk query is already running
+/// * Will return `FixerError::Custom` if the operation fails or does not finish within
+///   `bulk_timeout_secs`, or a line of the result cannot be parsed
+pub async fn fetch_shopify_products_bulk(
+    client: &AdminClient,
+    config: &Config,
+) -> Result<(Vec<ShopifyProduct>, Vec<Node>), FixerError> {
+    let bulk_query = format!(
+        r#"
+        {{
+            productVariants {{
+                edges {{
+                    node {}
+                }}
+            }}
+        }}"#,
+        variant_node_fields(&location_ids(config))
+    );
+    let run = serde_json::json!({
+        "query": r#"
+            mutation bulkOperationRunQuery($query: String!) {
+                bulkOperationRunQuery(query: $query) {
+                    bulkOperation {
+                        id
+                        status
+                        errorCode
+                        objectCount
+                        url
+                    }
+                    userErrors {
+                        field
+                        message
+                    }
+                }
+            }"#,
+        "variables": {
+            "query": bulk_query,
+        }
+    });
+    let data: BulkOperationRunQueryData = client.graphql(&run).await?;
+    check_user_errors(&data.bulk_operation_run_query.user_errors)?;
+    let mut operation = data
+        .bulk_operation_run_query
+        .bulk_operation
+        .ok_or(FixerError::Custom(
+            "Shopify did not start the bulk operation".to_string(),
+        ))?;
+
+    let poll = serde_json::json!({
+        "query": r#"
+            query ($id: ID!) {
+                node(id: $id) {
+                    ... on BulkOperation {
+                        id
+                        status
+                        errorCode
+                        objectCount
+                        url
+                    }
+                }
+            }"#,
+        "variables": {
+            "id": operation.id,
+        }
+    });
+    let deadline = tokio::time::Instant::now() + Duration::from_secs(config.bulk_timeout_secs);
+    while operation.status == "CREATED" || operation.status == "RUNNING" {
+        if tokio::time::Instant::now() >= deadline {
+            // Shopify runs one bulk query at a time, so a stuck one would block the next run too
+            let cancel = serde_json::json!({
+                "query": r#"
+                    mutation bulkOperationCancel($id: ID!) {
+                        bulkOperationCancel(id: $id) {
+                            userErrors {
+                                field
+                                message
+                            }
+                        }
+                    }"#,
+                "variables": {
+                    "id": operation.id,
+                }
+            });
+            let cancelled = client
+                .graphql::<BulkOperationCancelData>(&cancel)
+                .await
+                .is_ok_and(|c| check_user_errors(&c.bulk_operation_cancel.user_errors).is_ok());
+            return Err(FixerError::Custom(format!(
+                "Bulk operation {} did not finish within bulk_timeout_secs of {} seconds, with \
+                 status {} and {} objects. {}",
+                &operation.id,
+                config.bulk_timeout_secs,
+                &operation.status,
+                operation.object_count.as_deref().unwrap_or("0"),
+                if cancelled {
+                    "It was cancelled"
+                } else {
+                    "It could not be cancelled, so cancel it in Shopify before the next run"
+                }
+            )));
+        }
+        tokio::time::sleep(BULK_POLL_INTERVAL).await;
+        let data: BulkOperationNodeData = client.graphql(&poll).await?;
+        operation = data.node.ok_or(FixerError::Custom(format!(
+            "Bulk operation {} disappeared while polling",
+            &operation.id
+        )))?;
+    }
+    if operation.status != "COMPLETED" {
+        return Err(FixerError::Custom(format!(
+            "Bulk operation {} ended with status {} and error code {:?}",
+            &operation.id, &operation.status, &operation.error_code
+        )));
+    }
+
+    let mut failed_nodes = Vec::new();
+    let mut products = Vec::new();
+
+    // Shopify leaves out the url when the operation found no objects
+    let url = match operation.url {
+        Some(u) => u,
+        None => return Ok((products, failed_nodes)),
+    };
+    let jsonl = client.download(&url).await?;
+    for (i, line) in jsonl.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
         }
+        let node: Node = serde_json::from_str(line).map_err(|e| {
+            FixerError::Custom(format!(
+                "Cannot parse line {} of the bulk operation result: {}",
+                i + 1,
+                e
+            ))
+        })?;
+        push_node(node, config.currency, &mut products, &mut failed_nodes);
     }
 
     Ok((products, failed_nodes))
 }
 
-fn price_from_str(price_str: &str) -> Result<i64, ParseFloatError> {
-    let price_str: String = price_str
-        .chars()
-        .filter(|c| c.is_digit(10) || c == &'.')
+/// A new price and compare-at price for one variant, sent together with the other new prices of
+/// its product
+#[derive(Debug, Clone)]
+pub struct PriceUpdate {
+    /// The Shopify ID of the variant
+    pub variant_id: String,
+
+    /// The SKU to set for the variant. The SKU of the matching ABC product
+    pub sku: String,
+
+    /// The price to set for the variant. `None` to keep the price
+    pub price: Option<Money>,
+
+    /// The compare-at price to set for the variant. `Some(None)` removes it, and `None` keeps it
+    pub compare_at_price: Option<Option<Money>>,
+}
+
+/// The on hand quantity to set for an inventory item at one location
+#[derive(Debug, Clone)]
+pub struct LocationQuantity {
+    /// The Shopify ID of the location
+    pub location_id: String,
+
+    pub quantity: i64,
+}
+
+/// Send one `productVariantsBulkUpdate` mutation to change the price and compare-at price of
+/// several variants of the same Shopify product
+///
+/// # Arguments
+///
+/// * `client` - The `AdminClient` to send the mutation with
+/// * `product_id` - The Shopify ID of the product that owns every variant in `prices`
+/// * `prices` - The price changes to send
+///
+/// # Returns
+///
+/// The `UpdateShopifyPriceData` holding the variants Shopify updated
+///
+/// # Errors
+///
+/// * Will return `FixerError::UserErrors` if Shopify rejected the change
+/// * Will return a `FixerError` if sending the request fails or the response cannot be parsed
+pub async fn update_shopify_prices(
+    client: &AdminClient,
+    product_id: &str,
+    prices: &[PriceUpdate],
+) -> Result<UpdateShopifyPriceData, FixerError> {
+    let variants: Vec<serde_json::Value> = prices
+        .iter()
+        .map(|p| {
+            let mut variant = serde_json::json!({
+                "id": p.variant_id,
+                "inventoryItem": {
+                    "sku": p.sku,
+                },
+            });
+            if let Some(price) = p.price {
+                variant["price"] = serde_json::json!(price.amount());
+            }
+            if let Some(compare_at_price) = p.compare_at_price {
+                variant["compareAtPrice"] = serde_json::json!(compare_at_price.map(|c| c.amount()));
+            }
+            variant
+        })
         .collect();
-    let fprice: f64 = price_str.parse()?;
-    let iprice: i64 = (fprice * 100.0).round() as i64;
-    Ok(iprice)
+    let query = serde_json::json!({
+        "query": r#"
+            mutation productVariantsBulkUpdate($productId: ID!, $variants: [ProductVariantsBulkInput!]!) { 
+                productVariantsBulkUpdate(productId: $productId, variants: $variants) { 
+                    product { 
+                        id 
+                        status 
+                    } 
+                    productVariants { 
+                        id 
+                        sku 
+                        price 
+                        compareAtPrice 
+                    } 
+                    userErrors {
+                        field 
+                        message 
+                    } 
+                }
+            };"#,
+        "variables": {
+            "productId": product_id,
+            "variants": variants,
+        }
+    });
+
+    let data: UpdateShopifyPriceData = client.graphql(&query).await?;
+    check_user_errors(&data.product_variants_bulk_update.user_errors)?;
+    Ok(data)
 }
 
+/// Turn on inventory tracking for an inventory item, optionally set its unit cost, and set its on
+/// hand quantity at any number of locations
+///
+/// # Arguments
+///
+/// * `client` - The `AdminClient` to send the mutations with
+/// * `inventory_item_id` - The Shopify ID of the inventory item to update
+/// * `cost` - The unit cost to set, if it should change
+/// * `quantities` - The on hand quantities to set. If empty, no quantities are changed
+///
+/// # Returns
+///
+/// The responses of the `inventoryItemUpdate` and, if any quantities were set, the
+/// `inventorySetQuantities` mutations
+///
+/// # Errors
+///
+/// * Will return `FixerError::UserErrors` if Shopify rejected either change
+/// * Will return a `FixerError` if sending a request fails or a response cannot be parsed
+pub async fn update_shopify_inventory(
+    client: &AdminClient,
+    inventory_item_id: &str,
+    cost: Option<Money>,
+    quantities: &[LocationQuantity],
+) -> Result<(InventoryItemUpdateData, Option<InventorySetQuantitiesData>), FixerError> {
+    let mut input = serde_json::json!({
+        "tracked": true,
+    });
+    if let Some(cost) = cost {
+        input["cost"] = serde_json::json!(cost.amount());
+    }
+    let tracked_query = serde_json::json!({
+        "query": r#"
+            mutation inventoryItemUpdate($id: ID!, $input: InventoryItemInput!) {
+                inventoryItemUpdate(id: $id, input: $input) {
+                    inventoryItem {
+                        id 
+                        tracked 
+                        unitCost {
+                            amount
+                            currencyCode
+                        }
+                    }
+                    userErrors {
+                        field
+                        message
+                    }
+                }
+            }
+        "#,
+        "variables": {
+            "id": inventory_item_id,
+            "input": input,
+        }
+    });
+    let tracked_res: InventoryItemUpdateData = client.graphql(&tracked_query).await?;
+    check_user_errors(&tracked_res.inventory_item_update.user_errors)?;
+
+    if quantities.is_empty() {
+        return Ok((tracked_res, None));
+    }
+    let quantities: Vec<serde_json::Value> = quantities
+        .iter()
+        .map(|q| {
+            serde_json::json!({
+                "inventoryItemId": inventory_item_id,
+                "locationId": q.location_id,
+                "quantity": q.quantity,
+            })
+        })
+        .collect();
+    let query = serde_json::json!({
+        "query": r#"
+            mutation InventorySet($input: InventorySetQuantitiesInput!) {
+                inventorySetQuantities(input: $input) {
+                    inventoryAdjustmentGroup {
+                        createdAt
+                        changes {
+                            item {
+                                sku 
+                            }
+                            quantityAfterChange
+                        }
+                        reason
+                    }
+                    userErrors {
+                        field
+                        message
+                    }
+                }
+            }"#,
+        "variables": {
+            "input": {
+                "ignoreCompareQuantity": true,
+                "name": "on_hand",
+                "reason": "correction",
+                "quantities": quantities,
+            },
+        }
+    });
+    let res: InventorySetQuantitiesData = client.graphql(&query).await?;
+    check_user_errors(&res.inventory_set_quantities.user_errors)?;
+    Ok((tracked_res, Some(res)))
+}
+
+/// Read the ABC products from the item and posted item files generated by report 7-10
+///
+/// # Arguments
+///
+/// * `item_path` - The path to "item.data"
+/// * `posted_path` - The path to "item_posted.data"
+/// * `columns` - The encoding of both files, and where each field is read from in them and how it
+///   is parsed. Empty or zero promo prices are ignored
+/// * `currency` - The currency of every ABC price
+/// * `orphans` - What to do with posted rows whose SKU is not in "item.data". Posted rows of items
+///   skipped by `rejects` are always skipped too
+/// * `rejects` - Whether to fail or skip rows with a different number of columns than the rest,
+///   or with a field that cannot be parsed. An item whose posted row is skipped is left out, so
+///   its stock is not taken as 0. A posted row whose SKU cannot be read is never skipped
+/// * `unmatched` - Where the orphans and the items with no posted row are listed
+///
+/// # Returns
+///
+/// The ABC products keyed by SKU
+///
+/// # Errors
+///
+/// Will return `csv::Error` if either file cannot be read or does not have the columns in
+/// `columns`, if a row cannot be parsed and `rejects` is strict or the row is a posted row whose
+/// SKU cannot be read, or if `orphans` is
+/// `OrphanPolicy::Error` and a posted row has no item
 pub fn parse_abc_item_files(
     item_path: &str,
     posted_path: &str,
+    columns: &AbcColumns,
+    currency: Currency,
+    orphans: OrphanPolicy,
+    rejects: &mut Rejects,
+    unmatched: &mut Unmatched,
 ) -> Result<HashMap<String, AbcProduct>, csv::Error> {
+    let item_columns = &columns.item;
+    let item_bytes = columns.encoding.read(item_path)?;
     let mut item_data = csv::ReaderBuilder::new()
         .delimiter(b'\t')
-        .has_headers(false)
-        .from_path(item_path)?;
+        .has_headers(item_columns.has_headers)
+        .flexible(true)
+        .from_reader(item_bytes.as_slice());
+    let posted_columns = &columns.posted;
+    let posted_bytes = columns.encoding.read(posted_path)?;
     let mut posted_data = csv::ReaderBuilder::new()
         .delimiter(b'\t')
-        .has_headers(false)
-        .from_path(posted_path)?;
+        .has_headers(posted_columns.has_headers)
+        .flexible(true)
+        .from_reader(posted_bytes.as_slice());
+
+    // Check both files against the columns before reading any item, so a change to the layout
+    // of the export is reported as such instead of as a bad field
+    let item_schema = Schema::read(
+        "item.data",
+        &mut item_data,
+        item_columns.has_headers,
+        item_columns.column_count,
+    )?;
+    let sku_index = item_schema.index("sku", item_columns.sku.column())?;
+    let desc_index = item_schema.index("desc", item_columns.desc.column())?;
+    let list_index = item_schema.index("list", item_columns.list.column())?;
+    let cost_index = item_schema.index("cost", item_columns.cost.column())?;
+    let upcs_index = item_schema.index("upcs", item_columns.upcs.column())?;
+    let promo_index = match &item_columns.promo {
+        Some(p) => Some(item_schema.index("promo", p.column())?),
+        None => None,
+    };
+    let posted_schema = Schema::read(
+        "item_posted.data",
+        &mut posted_data,
+        posted_columns.has_headers,
+        posted_columns.column_count,
+    )?;
+    let posted_sku_index = posted_schema.index("sku", posted_columns.sku.column())?;
+    let stock_index = posted_schema.index("stock", posted_columns.stock.column())?;
 
-    let mut i = 0;
     let mut products = HashMap::new();
-    while let Some(row) = item_data.records().next() {
-        i += 1;
-        let row = row?;
-        let sku = row
-            .get(0)
-            .ok_or(csv::Error::custom(format!(
-                "Cannot deserialize sku in row {}",
-                i
-            )))?
-            .to_string();
-        let desc = row
-            .get(1)
-            .ok_or(csv::Error::custom(format!(
-                "Cannot deserialize desc in row {}",
-                i
-            )))?
-            .to_string();
-        let upc_str = row.get(43).ok_or(csv::Error::custom(format!(
-            "Cannot fetch upcs in row {}",
-            i
-        )))?;
-        let upcs: Vec<Upc> = Upc::from_abc_upc_list(upc_str)
-            .iter()
-            .filter_map(|upc| upc.to_owned())
-            .collect();
-        let list = row.get(6).ok_or(csv::Error::custom(format!(
-            "Cannot fetch list price from row {}",
-            i
-        )))?;
-        let list = price_from_str(list).or(Err(csv::Error::custom(format!(
-            "Cannot parse a price in cents for list in row {}",
-            i
-        ))))?;
-        let cost = row.get(8).ok_or(csv::Error::custom(format!(
-            "Cannot fetch cost from row {}",
-            i
-        )))?;
-        let cost = price_from_str(cost).or(Err(csv::Error::custom(format!(
-            "Cannot parse a price in cents for cost in row {}",
-            i
-        ))))?;
-
-        products.insert(
-            sku.clone(),
-            AbcProduct {
+    // The SKUs of items left out because one of their rows was rejected, so their other rows are
+    // not taken for orphans
+    let mut rejected_skus = HashSet::new();
+    for (i, row) in item_data.records().enumerate() {
+        let i = i + 1;
+        let rejected_sku = row
+            .as_ref()
+            .ok()
+            .and_then(|r| r.get(sku_index))
+            .map(|s| item_columns.sku.parser().parse(s));
+        let product = match row {
+            Err(e) if e.is_io_error() => return Err(e),
+            row => row.map_err(|e| item_schema.reject(i, "row", "", e)),
+        }
+        .and_then(|row| {
+            item_schema.check(&row, i)?;
+            let sku = item_columns.sku.parser().parse(&row[sku_index]);
+            let desc = item_columns.desc.parser().parse(&row[desc_index]);
+            let upcs = item_columns.upcs.parser().parse(&row[upcs_index]);
+            let list = item_columns
+                .list
+                .parser()
+                .parse(&row[list_index], currency)
+                .map_err(|e| item_schema.reject(i, "list", &row[list_index], e))?;
+            // ABC costs may have fractions of a cent, like "1.2345", but Shopify takes whole cents
+            let cost = item_columns
+                .cost
+                .parser()
+                .parse_rounded(&row[cost_index], currency)
+                .map_err(|e| item_schema.reject(i, "cost", &row[cost_index], e))?;
+            let promo = match (&item_columns.promo, promo_index) {
+                (Some(column), Some(c)) if !row[c].trim().is_empty() => Some(
+                    column
+                        .parser()
+                        .parse(&row[c], currency)
+                        .map_err(|e| item_schema.reject(i, "promo", &row[c], e))?,
+                ),
+                _ => None,
+            }
+            .filter(|p| p.cents() > 0);
+
+            Ok(AbcProduct {
                 sku,
                 desc,
                 upcs,
                 list,
-                cost,
+                cost: Some(cost),
                 stock: 0.0,
+                promo,
+            })
+        });
+        match product {
+            Ok(p) => {
+                products.insert(p.sku.clone(), p);
+            }
+            Err(reject) => {
+                rejects.add(reject)?;
+                rejected_skus.extend(rejected_sku);
+            }
+        }
+    }
+
+    let mut posted_skus = HashSet::new();
+    for (i, row) in posted_data.records().enumerate() {
+        let i = i + 1;
+        // A skipped posted row would leave its item with a stock of 0, so the item is left out
+        // too. If its SKU cannot be read, the item cannot be found, so stop instead
+        let cannot_skip = |reject: Reject| {
+            csv::Error::custom(format!(
+                "{}. The row cannot be skipped because its SKU cannot be read, so its item would \
+                 have its stock set to 0",
+                reject
+            ))
+        };
+        let row = match row {
+            Ok(r) => r,
+            Err(e) if e.is_io_error() => return Err(e),
+            Err(e) => {
+                let reject = posted_schema.reject(i, "row", "", e);
+                rejects.add(reject.clone())?;
+                return Err(cannot_skip(reject));
+            }
+        };
+        if let Err(reject) = posted_schema.check(&row, i) {
+            rejects.add(reject.clone())?;
+            let sku = row
+                .get(posted_sku_index)
+                .map(|s| posted_columns.sku.parser().parse(s))
+                .filter(|s| !s.trim().is_empty())
+                .ok_or_else(|| cannot_skip(reject))?;
+            products.remove(&sku);
+            rejected_skus.insert(sku);
+            continue;
+        }
+        let sku = posted_columns.sku.parser().parse(&row[posted_sku_index]);
+        let stock = match posted_columns.stock.parser().parse(&row[stock_index]) {
+            Ok(s) => s,
+            Err(raw) => {
+                rejects.add(posted_schema.reject(i, "stock", &raw, "Not a number"))?;
+                products.remove(&sku);
+                rejected_skus.insert(sku);
+                continue;
+            }
+        };
+        posted_skus.insert(sku.clone());
+        let mut existing_record = match products.get(&sku) {
+            Some(p) => p.clone(),
+            None if rejected_skus.contains(&sku) => continue,
+            None => match orphans {
+                OrphanPolicy::Ignore => continue,
+                OrphanPolicy::Warn => {
+                    unmatched.orphans.push((sku, i));
+                    continue;
+                }
+                OrphanPolicy::Error => {
+                    return Err(csv::Error::custom(format!(
+                        "Cannot find existing product for item with sku {} in row {} of \
+                         item_posted.data",
+                        &sku, i
+                    )))
+                }
             },
-        );
-    }
-
-    let mut i = 0;
-    while let Some(row) = posted_data.records().next() {
-        i += 1;
-        let row = row?;
-        let sku = row
-            .get(0)
-            .ok_or(csv::Error::custom(format!(
-                "Cannot deserialize sku in row {} of posted items",
-                i
-            )))?
-            .to_string();
-        let stock_str = row
-            .get(19)
-            .ok_or(csv::Error::custom(format!(
-                "Cannot deserialize stock in row {} of posted items",
-                i
-            )))?
-            .to_string();
-        let stock: f64 = stock_str.parse().or(Err(csv::Error::custom(format!(
-            "Cannot parse f64 from stock_str in row {} of posted items",
-            i
-        ))))?;
-        let mut existing_record = products
-            .get(&sku)
-            .ok_or(csv::Error::custom(format!(
-                "Cannot find existing product for item with sku {} in row {} of posted_data",
-                &sku, i
-            )))?
-            .clone();
+        };
         existing_record.stock = stock;
         existing_record.sku = existing_record.sku.to_uppercase();
         products.insert(sku, existing_record);
     }
+
+    unmatched.unposted = products
+        .keys()
+        .filter(|sku| !posted_skus.contains(*sku))
+        .cloned()
+        .collect();
+    unmatched.unposted.sort();
+    Ok(products)
+}
+
+/// Read the ABC products from the "TabOutput.tsv" file generated by report 1-15, which holds both
+/// the prices and the stock of each item
+///
+/// # Arguments
+///
+/// * `tab_output_path` - The path to "TabOutput.tsv"
+/// * `columns` - The encoding of the file, and where each field is read from in
+///   `columns.tab_output` and how it is parsed. Empty or zero promo prices are ignored
+/// * `currency` - The currency of every ABC price
+/// * `rejects` - Whether to fail or skip rows with a different number of columns than the rest,
+///   with a field that cannot be parsed, or with the SKU of an earlier row. The first row of a
+///   SKU is kept
+///
+/// # Returns
+///
+/// The ABC products keyed by uppercase SKU
+///
+/// # Errors
+///
+/// Will return `csv::Error` if the file cannot be read or does not have the columns in `columns`,
+/// or if `rejects` is strict and a row cannot be parsed or repeats a SKU
+pub fn parse_abc_tab_output(
+    tab_output_path: &str,
+    columns: &AbcColumns,
+    currency: Currency,
+    rejects: &mut Rejects,
+) -> Result<HashMap<String, AbcProduct>, csv::Error> {
+    let tab_columns = &columns.tab_output;
+    let tab_output_bytes = columns.encoding.read(tab_output_path)?;
+    let mut tab_output = csv::ReaderBuilder::new()
+        .delimiter(b'\t')
+        .has_headers(tab_columns.has_headers)
+        .flexible(true)
+        .from_reader(tab_output_bytes.as_slice());
+
+    let schema = Schema::read(
+        "TabOutput.tsv",
+        &mut tab_output,
+        tab_columns.has_headers,
+        tab_columns.column_count,
+    )?;
+    let sku_index = schema.index("sku", tab_columns.sku.column())?;
+    let desc_index = schema.index("desc", tab_columns.desc.column())?;
+    let list_index = schema.index("list", tab_columns.list.column())?;
+    let stock_index = schema.index("stock", tab_columns.stock.column())?;
+    let cost_index = match &tab_columns.cost {
+        Some(c) => Some(schema.index("cost", c.column())?),
+        None => None,
+    };
+    let upcs_index = match &tab_columns.upcs {
+        Some(u) => Some(schema.index("upcs", u.column())?),
+        None => None,
+    };
+    let promo_index = match &tab_columns.promo {
+        Some(p) => Some(schema.index("promo", p.column())?),
+        None => None,
+    };
+
+    let mut products = HashMap::new();
+    let mut first_rows = HashMap::new();
+    for (i, row) in tab_output.records().enumerate() {
+        let i = i + 1;
+        let product = match row {
+            Err(e) if e.is_io_error() => return Err(e),
+            row => row.map_err(|e| schema.reject(i, "row", "", e)),
+        }
+        .and_then(|row| {
+            schema.check(&row, i)?;
+            let sku = tab_columns
+                .sku
+                .parser()
+                .parse(&row[sku_index])
+                .to_uppercase();
+            let desc = tab_columns.desc.parser().parse(&row[desc_index]);
+            let list = tab_columns
+                .list
+                .parser()
+                .parse(&row[list_index], currency)
+                .map_err(|e| schema.reject(i, "list", &row[list_index], e))?;
+            let stock = tab_columns
+                .stock
+                .parser()
+                .parse(&row[stock_index])
+                .map_err(|raw| schema.reject(i, "stock", &raw, "Not a number"))?;
+            let cost = match (&tab_columns.cost, cost_index) {
+                (Some(column), Some(c)) => Some(
+                    column
+                        .parser()
+                        .parse_rounded(&row[c], currency)
+                        .map_err(|e| schema.reject(i, "cost", &row[c], e))?,
+                ),
+                _ => None,
+            };
+            let upcs = match (&tab_columns.upcs, upcs_index) {
+                (Some(column), Some(u)) => column.parser().parse(&row[u]),
+                _ => Vec::new(),
+            };
+            let promo = match (&tab_columns.promo, promo_index) {
+                (Some(column), Some(c)) if !row[c].trim().is_empty() => Some(
+                    column
+                        .parser()
+                        .parse(&row[c], currency)
+                        .map_err(|e| schema.reject(i, "promo", &row[c], e))?,
+                ),
+                _ => None,
+            }
+            .filter(|p| p.cents() > 0);
+
+            Ok(AbcProduct {
+                sku,
+                desc,
+                upcs,
+                list,
+                cost,
+                stock,
+                promo,
+            })
+        });
+        match product {
+            Ok(p) => match first_rows.get(&p.sku) {
+                Some(first) => rejects.add(schema.reject(
+                    i,
+                    "sku",
+                    &p.sku,
+                    format!("Duplicate of row {}", first),
+                ))?,
+                None => {
+                    first_rows.insert(p.sku.clone(), i);
+                    products.insert(p.sku.clone(), p);
+                }
+            },
+            Err(reject) => rejects.add(reject)?,
+        }
+    }
     Ok(products)
 }
 
 pub fn map_upcs(existing_map: &HashMap<String, AbcProduct>) -> HashMap<String, (bool, AbcProduct)> {
     let mut upc_map = HashMap::new();
-    for (_sku, product) in existing_map {
+    for product in existing_map.values() {
         for upc in product.upcs.iter() {
-            let dup = match upc_map.get(&upc.to_string()) {
-                Some(_) => true,
-                None => false,
-            };
+            let dup = upc_map.contains_key(&upc.to_string());
             upc_map.insert(upc.to_string(), (dup, product.to_owned()));
         }
     }
@@ -392,9 +1296,14 @@ pub struct AbcProduct {
     sku: String,
     desc: String,
     upcs: Vec<Upc>,
-    list: i64,
-    cost: i64,
+    list: Money,
+
+    /// The cost, unless it was read from an export without one
+    cost: Option<Money>,
     stock: f64,
+
+    /// The promotional price, if the configured promo column holds one
+    promo: Option<Money>,
 }
 
 impl AbcProduct {
@@ -410,26 +1319,32 @@ impl AbcProduct {
         self.upcs.to_vec()
     }
 
-    pub fn list(&self) -> i64 {
+    pub fn list(&self) -> Money {
         self.list
     }
 
-    pub fn cost(&self) -> i64 {
+    pub fn cost(&self) -> Option<Money> {
         self.cost
     }
 
     pub fn stock(&self) -> f64 {
         self.stock
     }
+
+    pub fn promo(&self) -> Option<Money> {
+        self.promo
+    }
 }
 
+#[derive(Default)]
 pub struct AbcProductBuilder {
     sku: Option<String>,
     desc: Option<String>,
     upcs: Vec<Upc>,
-    list: Option<i64>,
-    cost: Option<i64>,
+    list: Option<Money>,
+    cost: Option<Money>,
     stock: Option<f64>,
+    promo: Option<Money>,
 }
 
 impl AbcProductBuilder {
@@ -441,6 +1356,7 @@ impl AbcProductBuilder {
             list: None,
             cost: None,
             stock: None,
+            promo: None,
         }
     }
 
@@ -471,14 +1387,14 @@ impl AbcProductBuilder {
         }
     }
 
-    pub fn with_list(self, list: i64) -> Self {
+    pub fn with_list(self, list: Money) -> Self {
         AbcProductBuilder {
             list: Some(list),
             ..self
         }
     }
 
-    pub fn with_cost(self, cost: i64) -> Self {
+    pub fn with_cost(self, cost: Money) -> Self {
         AbcProductBuilder {
             cost: Some(cost),
             ..self
@@ -492,14 +1408,195 @@ impl AbcProductBuilder {
         }
     }
 
+    pub fn with_promo(self, promo: Money) -> Self {
+        AbcProductBuilder {
+            promo: Some(promo),
+            ..self
+        }
+    }
+
     pub fn build(self) -> Option<AbcProduct> {
         Some(AbcProduct {
             sku: self.sku.clone()?,
             desc: self.desc.clone()?,
             upcs: self.upcs,
             list: self.list?,
-            cost: self.cost?,
+            cost: self.cost,
             stock: self.stock?,
+            promo: self.promo,
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::abc_columns::TabOutputColumns;
+
+    /// Write an ABC file to a temporary path, one row per line, with tabs between the columns
+    fn write_abc_file(name: &str, rows: &[Vec<&str>]) -> String {
+        let path = std::env::temp_dir().join(format!(
+            "shopify-price-fixer-{}-{}",
+            std::process::id(),
+            name
+        ));
+        let text: Vec<String> = rows.iter().map(|r| r.join("\t")).collect();
+        std::fs::write(&path, text.join("\n")).unwrap();
+        path.to_string_lossy().into_owned()
+    }
+
+    /// An "item.data" row in the default layout
+    fn item_row<'a>(sku: &'a str, list: &'a str, cost: &'a str) -> Vec<&'a str> {
+        let mut row = vec![""; 50];
+        row[0] = sku;
+        row[1] = "Item";
+        row[6] = list;
+        row[8] = cost;
+        row
+    }
+
+    /// An "item_posted.data" row in the default layout
+    fn posted_row<'a>(sku: &'a str, stock: &'a str) -> Vec<&'a str> {
+        let mut row = vec![""; 25];
+        row[0] = sku;
+        row[19] = stock;
+        row
+    }
+
+    fn parse(
+        name: &str,
+        items: &[Vec<&str>],
+        posted: &[Vec<&str>],
+        rejects: &mut Rejects,
+    ) -> Result<HashMap<String, AbcProduct>, csv::Error> {
+        let item_path = write_abc_file(&format!("{}-item.data", name), items);
+        let posted_path = write_abc_file(&format!("{}-item_posted.data", name), posted);
+        parse_abc_item_files(
+            &item_path,
+            &posted_path,
+            &AbcColumns::default(),
+            Currency::USD,
+            OrphanPolicy::Warn,
+            rejects,
+            &mut Unmatched::default(),
+        )
+    }
+
+    #[test]
+    fn lenient_parse_leaves_out_item_with_truncated_posted_row() {
+        let items = [
+            item_row("A1", "12.99", "6.00"),
+            item_row("B2", "5.00", "2.50"),
+        ];
+        let posted = [posted_row("A1", "5"), vec!["B2", "", ""]];
+        let mut rejects = Rejects::lenient();
+        let products = parse("truncated", &items, &posted, &mut rejects).unwrap();
+
+        assert_eq!(products["A1"].stock, 5.0);
+        assert!(!products.contains_key("B2"));
+        assert_eq!(rejects.rows().len(), 1);
+        assert_eq!(rejects.rows()[0].row, 2);
+    }
+
+    #[test]
+    fn lenient_parse_fails_on_posted_row_without_sku() {
+        let items = [item_row("A1", "12.99", "6.00")];
+        let posted = [posted_row("A1", "5"), vec!["", ""]];
+        let mut rejects = Rejects::lenient();
+        assert!(parse("no-sku", &items, &posted, &mut rejects).is_err());
+    }
+
+    #[test]
+    fn strict_parse_fails_on_truncated_posted_row() {
+        let items = [item_row("A1", "12.99", "6.00")];
+        let posted = [posted_row("A1", "5"), vec!["A1", ""]];
+        let mut rejects = Rejects::strict();
+        assert!(parse("strict", &items, &posted, &mut rejects).is_err());
+    }
+
+    #[test]
+    fn costs_with_fractions_of_a_cent_are_rounded() {
+        let items = [
+            item_row("A1", "12.99", "1.2345"),
+            item_row("B2", "5.00", "1.235"),
+        ];
+        let posted = [posted_row("A1", "5"), posted_row("B2", "3")];
+        let mut rejects = Rejects::strict();
+        let products = parse("precise-cost", &items, &posted, &mut rejects).unwrap();
+
+        assert_eq!(products["A1"].cost().map(|c| c.cents()), Some(123));
+        assert_eq!(products["B2"].cost().map(|c| c.cents()), Some(124));
+    }
+
+    /// "tests/fixtures/TabOutput.tsv" is written in the layout the default column names assume,
+    /// not exported from ABC, so this only checks the parser against that assumption
+    const TAB_OUTPUT_FIXTURE: &str =
+        concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/TabOutput.tsv");
+
+    fn parse_tab_output(
+        path: &str,
+        columns: TabOutputColumns,
+        rejects: &mut Rejects,
+    ) -> Result<HashMap<String, AbcProduct>, csv::Error> {
+        let columns = AbcColumns {
+            tab_output: columns,
+            ..AbcColumns::default()
+        };
+        parse_abc_tab_output(path, &columns, Currency::USD, rejects)
+    }
+
+    #[test]
+    fn tab_output_fixture_is_read_with_the_default_columns() {
+        let mut rejects = Rejects::strict();
+        let products = parse_tab_output(
+            TAB_OUTPUT_FIXTURE,
+            TabOutputColumns::default(),
+            &mut rejects,
+        )
+        .unwrap();
+
+        assert_eq!(products.len(), 3);
+        assert_eq!(products["A1"].desc, "Hammer 16 oz");
+        assert_eq!(products["A1"].list.cents(), 1299);
+        assert_eq!(products["B2"].stock, 120.0);
+        assert_eq!(products["C3"].list.cents(), 129900);
+        assert!(products.values().all(|p| p.cost().is_none()));
+        assert!(products.values().all(|p| p.upcs.is_empty()));
+    }
+
+    #[test]
+    fn tab_output_fixture_reads_the_optional_columns_when_mapped() {
+        let columns: TabOutputColumns = serde_json::from_str(
+            r#"{ "cost": "Cost", "upcs": { "column": "UPC", "parser": "single" }, "promo": "Promo" }"#,
+        )
+        .unwrap();
+        let mut rejects = Rejects::strict();
+        let products = parse_tab_output(TAB_OUTPUT_FIXTURE, columns, &mut rejects).unwrap();
+
+        assert_eq!(products["A1"].cost().map(|c| c.cents()), Some(600));
+        assert_eq!(products["C3"].cost().map(|c| c.cents()), Some(64013));
+        assert_eq!(products["A1"].upcs.len(), 1);
+        assert_eq!(products["B2"].promo().map(|p| p.cents()), Some(450));
+        assert_eq!(products["C3"].promo(), None);
+    }
+
+    #[test]
+    fn tab_output_rows_repeating_a_sku_are_rejected() {
+        let rows = [
+            vec!["Sku", "Description", "List", "Stock"],
+            vec!["A1", "Hammer", "12.99", "5"],
+            vec!["a1", "Hammer again", "9.99", "2"],
+        ];
+        let path = write_abc_file("duplicate-TabOutput.tsv", &rows);
+
+        let mut rejects = Rejects::lenient();
+        let products = parse_tab_output(&path, TabOutputColumns::default(), &mut rejects).unwrap();
+        assert_eq!(products["A1"].list.cents(), 1299);
+        assert_eq!(rejects.rows().len(), 1);
+        assert_eq!(rejects.rows()[0].row, 2);
+        assert_eq!(rejects.rows()[0].field, "sku");
+
+        let mut rejects = Rejects::strict();
+        assert!(parse_tab_output(&path, TabOutputColumns::default(), &mut rejects).is_err());
+    }
+}
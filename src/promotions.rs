@@ -0,0 +1,299 @@
+use crate::money::{Currency, Money};
+use crate::product::AbcProduct;
+use crate::upc::Upc;
+use crate::FixerError;
+use chrono::{NaiveDate, NaiveDateTime};
+use serde::Deserialize;
+use std::path::Path;
+
+/// A sale price for one item between two dates
+#[derive(Debug, Clone)]
+pub struct Promotion {
+    /// The ABC SKU the promotion is for, if it is given by SKU
+    pub sku: Option<String>,
+
+    /// The UPC the promotion is for, if it is given by UPC
+    pub upc: Option<Upc>,
+
+    /// The sale price
+    pub price: Money,
+
+    /// When the promotion starts, in local time
+    pub start: NaiveDateTime,
+
+    /// When the promotion ends, in local time
+    pub end: NaiveDateTime,
+}
+
+/// Whether an item has a promotion running at some time
+#[derive(Debug, Clone)]
+pub enum PromotionStatus<'a> {
+    /// A promotion is running
+    Active(&'a Promotion),
+
+    /// No promotion is running, but one has ended
+    Ended(&'a Promotion),
+}
+
+/// Every promotion read from a promotions file
+#[derive(Debug, Clone, Default)]
+pub struct Promotions {
+    pub promotions: Vec<Promotion>,
+}
+
+/// A row of the promotions file as it is written
+#[derive(Deserialize, Debug)]
+struct PromotionRow {
+    #[serde(default)]
+    sku: String,
+    #[serde(default)]
+    upc: String,
+    price: String,
+    start: String,
+    end: String,
+}
+
+/// Parse a date like "2024-10-05" or a date and time like "2024-10-05 08:00"
+///
+/// # Arguments
+///
+/// * `date_str` - The date to parse
+/// * `end_of_day` - Whether a date without a time means the end of that day instead of the start
+fn parse_date(date_str: &str, end_of_day: bool) -> Option<NaiveDateTime> {
+    let date_str = date_str.trim();
+    if let Ok(d) = NaiveDateTime::parse_from_str(date_str, "%Y-%m-%d %H:%M") {
+        return Some(d);
+    }
+    let date = NaiveDate::parse_from_str(date_str, "%Y-%m-%d").ok()?;
+    if end_of_day {
+        date.and_hms_opt(23, 59, 59)
+    } else {
+        date.and_hms_opt(0, 0, 0)
+    }
+}
+
+impl Promotion {
+    /// Check whether the promotion is for an ABC product, by its SKU or any of its UPCs
+    pub fn matches(&self, abc_product: &AbcProduct) -> bool {
+        let by_sku = self
+            .sku
+            .as_ref()
+            .is_some_and(|s| s.eq_ignore_ascii_case(&abc_product.sku()));
+        let by_upc = self
+            .upc
+            .as_ref()
+            .is_some_and(|u| abc_product.upcs().contains(u));
+        by_sku || by_upc
+    }
+}
+
+impl std::fmt::Display for Promotion {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "promotion price {} from {} to {}",
+            self.price,
+            self.start.format("%Y-%m-%d %H:%M"),
+            self.end.format("%Y-%m-%d %H:%M")
+        )
+    }
+}
+
+impl Promotions {
+    /// Read a promotions file. It is a CSV file with the headers "sku", "upc", "price", "start" and
+    /// "end". Each row needs a SKU or a UPC. Dates are in local time, like "2024-10-05" or
+    /// "2024-10-05 08:00". An end date without a time lasts until the end of that day
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The path to the promotions file
+    /// * `currency` - The currency of every price in the file
+    ///
+    /// # Errors
+    ///
+    /// Returns `FixerError::Custom` if the file cannot be read or any row is not valid
+    pub fn read(path: &Path, currency: Currency) -> Result<Self, FixerError> {
+        let mut reader = csv::ReaderBuilder::new()
+            .trim(csv::Trim::All)
+            .from_path(path)
+            .map_err(|e| {
+                FixerError::Custom(format!("Could not read promotions file {:?}: {}", path, e))
+            })?;
+
+        let mut promotions = Vec::new();
+        for (i, row) in reader.deserialize::<PromotionRow>().enumerate() {
+            // Count the header as the first line
+            let line = i + 2;
+            let invalid = |field: &str| {
+                FixerError::Custom(format!(
+                    "Invalid {} on line {} of promotions file {:?}",
+                    field, line, path
+                ))
+            };
+            let row = row.map_err(|e| {
+                FixerError::Custom(format!(
+                    "Cannot parse line {} of promotions file {:?}: {}",
+                    line, path, e
+                ))
+            })?;
+
+            let sku = Some(row.sku).filter(|s| !s.is_empty());
+            let upc = match row.upc.as_str() {
+                "" => None,
+                u => Some(Upc::try_from(u).map_err(|_| invalid("upc"))?),
+            };
+            if sku.is_none() && upc.is_none() {
+                return Err(invalid("sku or upc"));
+            }
+            let promotion = Promotion {
+                sku,
+                upc,
+                price: Money::parse(&row.price, currency).map_err(|_| invalid("price"))?,
+                start: parse_date(&row.start, false).ok_or(invalid("start"))?,
+                end: parse_date(&row.end, true).ok_or(invalid("end"))?,
+            };
+            if promotion.end < promotion.start {
+                return Err(invalid("end, which is before the start,"));
+            }
+            promotions.push(promotion);
+        }
+        Ok(Promotions { promotions })
+    }
+
+    /// Find whether an ABC product has a promotion running
+    ///
+    /// # Arguments
+    ///
+    /// * `abc_product` - The ABC product to look up
+    /// * `now` - The local time of the run
+    ///
+    /// # Returns
+    ///
+    /// The first running promotion for the product, otherwise the promotion that ended last, or
+    /// `None` if the product has neither
+    pub fn status(
+        &self,
+        abc_product: &AbcProduct,
+        now: NaiveDateTime,
+    ) -> Option<PromotionStatus<'_>> {
+        let mut ended: Option<&Promotion> = None;
+        for promotion in self.promotions.iter().filter(|p| p.matches(abc_product)) {
+            if promotion.start <= now && now <= promotion.end {
+                return Some(PromotionStatus::Active(promotion));
+            }
+            if promotion.end < now && ended.is_none_or(|e| e.end < promotion.end) {
+                ended = Some(promotion);
+            }
+        }
+        ended.map(PromotionStatus::Ended)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::product::AbcProductBuilder;
+
+    /// Write a promotions file to a temporary path and read it back
+    fn read_promotions(name: &str, csv: &str) -> Promotions {
+        let path = std::env::temp_dir().join(format!(
+            "shopify-price-fixer-{}-{}.csv",
+            std::process::id(),
+            name
+        ));
+        std::fs::write(&path, csv).unwrap();
+        Promotions::read(&path, Currency::USD).unwrap()
+    }
+
+    fn abc(upcs: Vec<&str>) -> AbcProduct {
+        AbcProductBuilder::new()
+            .with_sku("A1")
+            .with_desc("Item")
+            .with_upcs(
+                upcs.into_iter()
+                    .map(|u| Upc::try_from(u).unwrap())
+                    .collect(),
+            )
+            .with_list(Money::new(1000, Currency::USD))
+            .with_stock(1.0)
+            .build()
+            .unwrap()
+    }
+
+    fn at(date: &str) -> NaiveDateTime {
+        NaiveDateTime::parse_from_str(date, "%Y-%m-%d %H:%M:%S").unwrap()
+    }
+
+    fn active_price(status: Option<PromotionStatus<'_>>) -> Option<i64> {
+        match status {
+            Some(PromotionStatus::Active(p)) => Some(p.price.cents()),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn date_without_a_time_starts_and_ends_the_day() {
+        assert_eq!(
+            parse_date("2024-10-05", false),
+            Some(at("2024-10-05 00:00:00"))
+        );
+        assert_eq!(
+            parse_date("2024-10-05", true),
+            Some(at("2024-10-05 23:59:59"))
+        );
+        assert_eq!(
+            parse_date(" 2024-10-05 08:00 ", true),
+            Some(at("2024-10-05 08:00:00"))
+        );
+        assert_eq!(parse_date("10/05/2024", false), None);
+    }
+
+    #[test]
+    fn promotion_runs_from_its_start_to_its_end() {
+        let promotions = read_promotions(
+            "boundaries",
+            "sku,upc,price,start,end\nA1,,8.99,2024-10-05 08:00,2024-10-06\n",
+        );
+        let abc_product = abc(Vec::new());
+        let status = |now: &str| promotions.status(&abc_product, at(now));
+
+        assert!(status("2024-10-05 07:59:59").is_none());
+        assert_eq!(active_price(status("2024-10-05 08:00:00")), Some(899));
+        assert_eq!(active_price(status("2024-10-06 23:59:59")), Some(899));
+        assert!(matches!(
+            status("2024-10-07 00:00:00"),
+            Some(PromotionStatus::Ended(_))
+        ));
+    }
+
+    #[test]
+    fn promotion_is_found_by_upc() {
+        let promotions = read_promotions(
+            "upc",
+            "sku,upc,price,start,end\n,012345678905,19.99,2024-10-05,2024-10-07\n",
+        );
+        let now = at("2024-10-06 12:00:00");
+        assert_eq!(
+            active_price(promotions.status(&abc(vec!["012345678905"]), now)),
+            Some(1999)
+        );
+        assert!(promotions.status(&abc(Vec::new()), now).is_none());
+    }
+
+    #[test]
+    fn active_promotion_beats_an_ended_one() {
+        let promotions = read_promotions(
+            "active-and-ended",
+            "sku,upc,price,start,end\nA1,,3.00,2024-10-01,2024-10-03\nA1,,8.99,2024-10-05,2024-10-07\n",
+        );
+        let abc_product = abc(Vec::new());
+        assert_eq!(
+            active_price(promotions.status(&abc_product, at("2024-10-06 12:00:00"))),
+            Some(899)
+        );
+        match promotions.status(&abc_product, at("2024-10-08 12:00:00")) {
+            Some(PromotionStatus::Ended(p)) => assert_eq!(p.price.cents(), 899),
+            _ => panic!("the promotion that ended last should be found"),
+        }
+    }
+}
@@ -0,0 +1,213 @@
+use crate::FixerError;
+use rand::Rng;
+use serde::Deserialize;
+use std::time::Duration;
+
+/// How admin API requests are retried when they fail for a reason that is likely to pass. Such as
+/// a dropped connection, a 5xx or 429 status, or a THROTTLED GraphQL error
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct RetryPolicy {
+    /// The most times a request is sent, including the first. Set to 1 to never retry
+    pub attempts: u32,
+
+    /// How long to wait before the first retry, in milliseconds. Doubles after every attempt
+    pub initial_backoff_ms: u64,
+
+    /// The longest to ever wait between attempts, in milliseconds
+    pub max_backoff_ms: u64,
+
+    /// A random share of the backoff, from 0.0 to 1.0, added to each wait so that retries do not
+    /// line up
+    pub jitter: f64,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            attempts: 5,
+            initial_backoff_ms: 500,
+            max_backoff_ms: 30_000,
+            jitter: 0.25,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Calculate how long to wait before retrying
+    ///
+    /// # Arguments
+    ///
+    /// * `attempt` - How many attempts have been made so far. Starts at 1
+    /// * `retry_after` - The wait requested by Shopify in a `Retry-After` header, if any. Used
+    ///   instead of the backoff when it is longer
+    ///
+    /// # Returns
+    ///
+    /// The `Duration` to sleep before the next attempt
+    pub fn delay(&self, attempt: u32, retry_after: Option<Duration>) -> Duration {
+        let exponent = attempt.saturating_sub(1).min(31);
+        let backoff = self
+            .initial_backoff_ms
+            .saturating_mul(1 << exponent)
+            .min(self.max_backoff_ms);
+        let jitter = self.jitter.clamp(0.0, 1.0);
+        let jitter = if jitter > 0.0 {
+            rand::thread_rng().gen_range(0.0..=jitter)
+        } else {
+            0.0
+        };
+        let delay = Duration::from_millis(backoff).mul_f64(1.0 + jitter);
+
+        match retry_after {
+            Some(r) if r > delay => r,
+            _ => delay,
+        }
+    }
+
+    /// Run `request` until it succeeds, fails with an error that is not transient, or runs out of
+    /// attempts
+    ///
+    /// # Arguments
+    ///
+    /// * `request` - Builds and sends the request. Called once per attempt
+    ///
+    /// # Returns
+    ///
+    /// The result of the first successful attempt
+    ///
+    /// # Errors
+    ///
+    /// Returns the error of the last attempt, wrapped in `FixerError::RetriesExhausted` if every
+    /// attempt failed with a transient error
+    pub async fn run<T, F, Fut>(&self, mut request: F) -> Result<T, FixerError>
+    where
+        F: FnMut() -> Fut,
+        Fut: std::future::Future<Output = Result<T, FixerError>>,
+    {
+        let attempts = self.attempts.max(1);
+        let mut attempt = 0;
+        loop {
+            attempt += 1;
+            match request().await {
+                Ok(t) => return Ok(t),
+                Err(e) if !e.is_transient() => return Err(e),
+                Err(e) if attempt >= attempts => {
+                    return Err(FixerError::RetriesExhausted {
+                        attempts,
+                        last: Box::new(e),
+                    })
+                }
+                Err(e) => tokio::time::sleep(self.delay(attempt, e.retry_after())).await,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+
+    fn policy(attempts: u32, jitter: f64) -> RetryPolicy {
+        RetryPolicy {
+            attempts,
+            initial_backoff_ms: 100,
+            max_backoff_ms: 1_000,
+            jitter,
+        }
+    }
+
+    fn unavailable() -> FixerError {
+        FixerError::Http {
+            status: 503,
+            retry_after: None,
+        }
+    }
+
+    #[test]
+    fn backoff_doubles_up_to_the_limit() {
+        let policy = policy(10, 0.0);
+        let delays: Vec<u128> = (1..=6).map(|a| policy.delay(a, None).as_millis()).collect();
+        assert_eq!(delays, [100, 200, 400, 800, 1_000, 1_000]);
+        assert_eq!(policy.delay(u32::MAX, None).as_millis(), 1_000);
+    }
+
+    #[test]
+    fn jitter_stays_within_its_share() {
+        let policy = policy(10, 0.5);
+        for _ in 0..100 {
+            let delay = policy.delay(2, None).as_millis();
+            assert!((200..=300).contains(&delay), "{}", delay);
+        }
+    }
+
+    #[test]
+    fn longer_retry_after_wins() {
+        let policy = policy(10, 0.0);
+        assert_eq!(
+            policy.delay(1, Some(Duration::from_secs(2))),
+            Duration::from_secs(2)
+        );
+        assert_eq!(
+            policy.delay(3, Some(Duration::from_millis(50))),
+            Duration::from_millis(400)
+        );
+    }
+
+    #[tokio::test]
+    async fn error_that_is_not_transient_is_not_retried() {
+        let calls = Cell::new(0);
+        let result: Result<(), FixerError> = policy(5, 0.0)
+            .run(|| async {
+                calls.set(calls.get() + 1);
+                Err(FixerError::Custom("bad query".to_string()))
+            })
+            .await;
+        assert!(matches!(result, Err(FixerError::Custom(_))));
+        assert_eq!(calls.get(), 1);
+    }
+
+    #[tokio::test]
+    async fn last_error_is_wrapped_after_every_attempt() {
+        let calls = Cell::new(0);
+        let policy = RetryPolicy {
+            initial_backoff_ms: 1,
+            ..policy(3, 0.0)
+        };
+        let result: Result<(), FixerError> = policy
+            .run(|| async {
+                calls.set(calls.get() + 1);
+                Err(unavailable())
+            })
+            .await;
+        match result {
+            Err(FixerError::RetriesExhausted { attempts, last }) => {
+                assert_eq!(attempts, 3);
+                assert!(matches!(*last, FixerError::Http { status: 503, .. }));
+            }
+            _ => panic!("expected RetriesExhausted"),
+        }
+        assert_eq!(calls.get(), 3);
+    }
+
+    #[tokio::test]
+    async fn transient_error_is_retried_until_it_passes() {
+        let calls = Cell::new(0);
+        let policy = RetryPolicy {
+            initial_backoff_ms: 1,
+            ..policy(3, 0.0)
+        };
+        let result = policy
+            .run(|| async {
+                calls.set(calls.get() + 1);
+                if calls.get() < 2 {
+                    Err(unavailable())
+                } else {
+                    Ok(calls.get())
+                }
+            })
+            .await;
+        assert_eq!(result.unwrap(), 2);
+    }
+}
@@ -0,0 +1,191 @@
+use crate::money::{format_cents, parse_cents, Money};
+use serde::{Deserialize, Deserializer};
+
+/// How a price computed from ABC is rounded so prices end consistently. Every strategy works in
+/// cents of the currency of the price, and prices of zero or less are never rounded
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "strategy", rename_all = "snake_case")]
+pub enum Rounding {
+    /// Round up to the next price ending in .99
+    NinetyNine,
+
+    /// Round up to the next price ending in .49 or .99
+    FortyNineNinetyNine,
+
+    /// Round to the nearest multiple of 25 cents, rounding halves up
+    NearestQuarter,
+
+    /// Round up to the next price ending in `cents`, like 95 for .95 or 0 for whole dollars
+    Ending { cents: i64 },
+
+    /// Round with the strategy of the first band the price is not above
+    Bands { bands: Vec<PriceBand> },
+}
+
+/// The rounding strategy for prices up to a limit
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct PriceBand {
+    /// The highest price in the band, like "20.00", in the currency of the price that is rounded.
+    /// Leave blank for a band with no upper limit
+    #[serde(default, deserialize_with = "deserialize_optional_cents")]
+    pub up_to: Option<i64>,
+
+    pub rounding: Rounding,
+}
+
+fn deserialize_optional_cents<'de, D>(deserializer: D) -> Result<Option<i64>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    match Option::<String>::deserialize(deserializer)? {
+        Some(price) => parse_cents(&price)
+            .map(Some)
+            .map_err(|_| serde::de::Error::custom(format!("Could not parse price from {}", price))),
+        None => Ok(None),
+    }
+}
+
+/// Round up to the next price whose cents are `ending`
+fn round_up_to_ending(price: i64, ending: i64) -> i64 {
+    let rounded = price - price % 100 + ending.rem_euclid(100);
+    if rounded < price {
+        rounded + 100
+    } else {
+        rounded
+    }
+}
+
+impl Rounding {
+    /// Round a price
+    ///
+    /// # Arguments
+    ///
+    /// * `price` - The price to round
+    ///
+    /// # Returns
+    ///
+    /// The rounded price, in the same currency
+    pub fn round(&self, price: Money) -> Money {
+        Money::new(self.round_cents(price.cents()), price.currency())
+    }
+
+    fn round_cents(&self, price: i64) -> i64 {
+        if price <= 0 {
+            return price;
+        }
+        match self {
+            Rounding::NinetyNine => round_up_to_ending(price, 99),
+            Rounding::FortyNineNinetyNine => {
+                round_up_to_ending(price, 49).min(round_up_to_ending(price, 99))
+            }
+            Rounding::NearestQuarter => (price * 2 + 25) / 50 * 25,
+            Rounding::Ending { cents } => round_up_to_ending(price, *cents),
+            Rounding::Bands { bands } => match bands
+                .iter()
+                .find(|b| b.up_to.is_none_or(|up_to| price <= up_to))
+            {
+                Some(band) => band.rounding.round_cents(price),
+                None => price,
+            },
+        }
+    }
+}
+
+impl std::fmt::Display for Rounding {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Rounding::NinetyNine => write!(f, "rounded up to .99"),
+            Rounding::FortyNineNinetyNine => write!(f, "rounded up to .49 or .99"),
+            Rounding::NearestQuarter => write!(f, "rounded to the nearest quarter"),
+            Rounding::Ending { cents } => write!(f, "rounded up to .{:02}", cents.rem_euclid(100)),
+            Rounding::Bands { bands } => {
+                write!(f, "rounded by price band (")?;
+                for (i, band) in bands.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    match band.up_to {
+                        Some(up_to) => {
+                            write!(f, "up to {} {}", format_cents(up_to), band.rounding)?
+                        }
+                        None => write!(f, "above that {}", band.rounding)?,
+                    }
+                }
+                write!(f, ")")
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ninety_nine_rounds_up() {
+        let r = Rounding::NinetyNine;
+        assert_eq!(r.round_cents(1000), 1099);
+        assert_eq!(r.round_cents(1099), 1099);
+        assert_eq!(r.round_cents(1100), 1199);
+        assert_eq!(r.round_cents(1), 99);
+    }
+
+    #[test]
+    fn forty_nine_ninety_nine_rounds_up_to_the_nearer_ending() {
+        let r = Rounding::FortyNineNinetyNine;
+        assert_eq!(r.round_cents(1000), 1049);
+        assert_eq!(r.round_cents(1049), 1049);
+        assert_eq!(r.round_cents(1050), 1099);
+        assert_eq!(r.round_cents(1099), 1099);
+    }
+
+    #[test]
+    fn nearest_quarter_rounds_halves_up() {
+        let r = Rounding::NearestQuarter;
+        assert_eq!(r.round_cents(1012), 1000);
+        assert_eq!(r.round_cents(1013), 1025);
+        assert_eq!(r.round_cents(1037), 1025);
+        assert_eq!(r.round_cents(1038), 1050);
+    }
+
+    #[test]
+    fn ending_rounds_up() {
+        assert_eq!(Rounding::Ending { cents: 0 }.round_cents(1000), 1000);
+        assert_eq!(Rounding::Ending { cents: 0 }.round_cents(1001), 1100);
+        assert_eq!(Rounding::Ending { cents: 95 }.round_cents(1096), 1195);
+    }
+
+    #[test]
+    fn bands_use_the_first_band_the_price_is_not_above() {
+        let r: Rounding = serde_json::from_str(
+            r#"{
+                "strategy": "bands",
+                "bands": [
+                    { "up_to": "20.00", "rounding": { "strategy": "ninety_nine" } },
+                    { "up_to": "100.00", "rounding": { "strategy": "forty_nine_ninety_nine" } },
+                    { "rounding": { "strategy": "ending", "cents": 0 } }
+                ]
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(r.round_cents(1500), 1599);
+        assert_eq!(r.round_cents(2000), 2099);
+        assert_eq!(r.round_cents(5010), 5049);
+        assert_eq!(r.round_cents(15001), 15100);
+    }
+
+    #[test]
+    fn rounding_keeps_the_currency() {
+        let cad = "CAD".parse().unwrap();
+        assert_eq!(
+            Rounding::NinetyNine.round(Money::new(1000, cad)),
+            Money::new(1099, cad)
+        );
+    }
+
+    #[test]
+    fn prices_of_zero_or_less_are_not_rounded() {
+        assert_eq!(Rounding::NinetyNine.round_cents(0), 0);
+        assert_eq!(Rounding::NearestQuarter.round_cents(-5), -5);
+    }
+}
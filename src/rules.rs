@@ -0,0 +1,1090 @@
+use crate::money::{format_cents, parse_cents, Money};
+use crate::product::{AbcProduct, ShopifyProduct};
+use crate::promotions::PromotionStatus;
+use crate::rounding::Rounding;
+use crate::FixerError;
+use serde::{Deserialize, Deserializer};
+use std::fs;
+use std::path::Path;
+
+/// The name of the rule used for variants no rule in the rule file matches
+pub const DEFAULT_RULE: &str = "default";
+
+/// The most a price may drop in one run unless the rule file says otherwise, in percent
+pub const DEFAULT_MAX_DECREASE_PERCENT: f64 = 20.0;
+
+fn default_max_decrease_percent() -> f64 {
+    DEFAULT_MAX_DECREASE_PERCENT
+}
+
+/// The pricing rules read from a rule file. Rules are tried in order and the first one that
+/// matches a variant decides its price
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct PricingRules {
+    #[serde(default)]
+    pub rules: Vec<PricingRule>,
+
+    #[serde(default)]
+    pub decreases: DecreaseGuard,
+
+    /// Optional. The lowest price allowed over the ABC cost. If left blank, prices may be anything
+    #[serde(default)]
+    pub margin_floor: Option<MarginFloor>,
+
+    /// Optional. How prices computed from ABC are rounded, unless the rule that decides them sets
+    /// its own. If left blank, prices are not rounded
+    #[serde(default)]
+    pub rounding: Option<Rounding>,
+
+    #[serde(default)]
+    pub sale: SalePolicy,
+}
+
+/// How variants on sale in Shopify and ABC promotional prices are priced
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct SalePolicy {
+    /// What to do with variants whose compare-at price is above their price
+    #[serde(default)]
+    pub on_sale: OnSale,
+
+    /// Set the price of variants with an ABC promo price to it, and their compare-at price to the
+    /// regular price the rules decide. The sale of a variant with no ABC promo price is ended
+    #[serde(default)]
+    pub promo_prices: bool,
+}
+
+/// What to do with a variant that is on sale in Shopify
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum OnSale {
+    /// Leave the price and compare-at price alone
+    #[default]
+    Keep,
+
+    /// Price the variant like any other, keeping its compare-at price
+    Reprice,
+
+    /// Price the variant like any other, and remove its compare-at price to end the sale
+    End,
+}
+
+/// The lowest price any rule may sell a variant at, as a percentage over its ABC cost
+#[derive(Deserialize, Debug, Clone)]
+pub struct MarginFloor {
+    /// How far over the ABC cost the price must be, in percent. 25 means at least cost plus 25%
+    pub min_percent_over_cost: f64,
+
+    #[serde(default)]
+    pub action: FloorAction,
+}
+
+/// What to do with a price below the margin floor
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum FloorAction {
+    /// Raise the price to the floor
+    #[default]
+    Raise,
+
+    /// Keep the price, and only list it in the margin floor log
+    Flag,
+}
+
+/// Limits on how far any rule may lower prices in one run, so a corrupt ABC export cannot cut
+/// prices across the whole catalog
+#[derive(Deserialize, Debug, Clone)]
+pub struct DecreaseGuard {
+    /// The most a price may drop in one run, in percent of the Shopify price. Larger decreases are
+    /// cut to this, so the price reaches its target over several runs. 100 allows any decrease. If
+    /// left blank, assume `DEFAULT_MAX_DECREASE_PERCENT`
+    #[serde(default = "default_max_decrease_percent")]
+    pub max_percent: f64,
+
+    /// If given, only these SKUs may have their price lowered
+    #[serde(default)]
+    pub allow_skus: Option<Vec<String>>,
+
+    /// These SKUs never have their price lowered
+    #[serde(default)]
+    pub deny_skus: Vec<String>,
+}
+
+/// A pricing policy and the variants it applies to. Every condition that is set must match, and a
+/// rule with no conditions matches every variant
+#[derive(Deserialize, Debug, Clone)]
+pub struct PricingRule {
+    /// Shown in the reason of every change the rule decides
+    pub name: String,
+
+    /// Matches SKUs that start with this, ignoring case
+    #[serde(default)]
+    pub sku_prefix: Option<String>,
+
+    /// Matches products from this Shopify vendor, ignoring case
+    #[serde(default)]
+    pub vendor: Option<String>,
+
+    /// Matches products of this Shopify product type, ignoring case
+    #[serde(default)]
+    pub product_type: Option<String>,
+
+    /// Matches products with this Shopify tag, ignoring case
+    #[serde(default)]
+    pub tag: Option<String>,
+
+    /// Optional. How prices this rule computes from ABC are rounded, instead of the `rounding` of
+    /// the rule file
+    #[serde(default)]
+    pub rounding: Option<Rounding>,
+
+    #[serde(flatten)]
+    pub policy: PricingPolicy,
+}
+
+/// How a rule sets the price of the variants it matches
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "policy", rename_all = "snake_case")]
+pub enum PricingPolicy {
+    /// Raise the Shopify price to the ABC list price, but never lower it
+    RaiseOnly,
+
+    /// Set the Shopify price to the ABC list price, raising or lowering it
+    ExactSync,
+
+    /// Set the Shopify price to the ABC cost plus `percent` percent of it. `percent` may not be
+    /// negative
+    Markup { percent: f64 },
+
+    /// Always set the Shopify price to `price`, like "19.99", in the currency of the store.
+    /// `price` must be above 0
+    Fixed {
+        #[serde(deserialize_with = "deserialize_cents")]
+        price: i64,
+    },
+}
+
+/// The price a rule decided for one variant
+#[derive(Debug, Clone, PartialEq)]
+pub struct PriceDecision {
+    /// The name of the rule that matched, or `DEFAULT_RULE`
+    pub rule: String,
+
+    pub policy: PricingPolicy,
+
+    /// How the price computed from ABC was rounded, if it was
+    pub rounding: Option<Rounding>,
+
+    /// The price the variant should have
+    pub price: Money,
+
+    /// Why the `DecreaseGuard` kept the price above what the rule decided, if it did
+    pub guarded: Option<String>,
+
+    /// The margin floor, if the price the rule decided is below it. With `FloorAction::Raise`
+    /// the `price` has already been raised to it
+    pub below_floor: Option<(Money, FloorAction)>,
+
+    /// The compare-at price the variant should have. `Some(None)` removes it, and `None` keeps it
+    pub compare_at_price: Option<Option<Money>>,
+
+    /// Where the sale price came from, if `price` is one. Like "ABC promo price 4.99"
+    pub promo: Option<String>,
+
+    /// The promotion that ended, if the regular price is put back because of it
+    pub ended_promotion: Option<String>,
+
+    /// Whether the variant is on sale and was left alone because of `OnSale::Keep`
+    pub kept_sale: bool,
+
+    /// The MAP, if the price was raised to it
+    pub below_map: Option<Money>,
+
+    /// The ABC cost, if the rule marks it up but it is 0 or below. The Shopify price is kept
+    /// instead
+    pub unusable_cost: Option<Money>,
+}
+
+fn deserialize_cents<'de, D>(deserializer: D) -> Result<i64, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let price = String::deserialize(deserializer)?;
+    parse_cents(&price)
+        .map_err(|_| serde::de::Error::custom(format!("Could not parse price from {}", price)))
+}
+
+impl PricingRule {
+    /// Check whether every condition of the rule matches a variant
+    ///
+    /// # Arguments
+    ///
+    /// * `shopify_product` - The Shopify variant
+    /// * `abc_product` - The ABC product matched to the variant
+    pub fn matches(&self, shopify_product: &ShopifyProduct, abc_product: &AbcProduct) -> bool {
+        let same = |want: &Option<String>, have: &str| {
+            want.as_ref().is_none_or(|w| w.eq_ignore_ascii_case(have))
+        };
+        self.sku_prefix.as_ref().is_none_or(|p| {
+            abc_product
+                .sku()
+                .to_uppercase()
+                .starts_with(&p.to_uppercase())
+        }) && same(&self.vendor, &shopify_product.vendor)
+            && same(&self.product_type, &shopify_product.product_type)
+            && self.tag.as_ref().is_none_or(|t| {
+                shopify_product
+                    .tags
+                    .iter()
+                    .any(|tag| tag.eq_ignore_ascii_case(t))
+            })
+    }
+}
+
+impl PricingPolicy {
+    /// Calculate the price of a variant under this policy
+    ///
+    /// # Arguments
+    ///
+    /// * `shopify_product` - The Shopify variant
+    /// * `abc_product` - The ABC product matched to the variant
+    /// * `rounding` - How to round a price computed from ABC. A fixed price and a Shopify price
+    ///   that is kept are never rounded
+    ///
+    /// # Returns
+    ///
+    /// The price, in the currency of the Shopify price
+    pub fn price(
+        &self,
+        shopify_product: &ShopifyProduct,
+        abc_product: &AbcProduct,
+        rounding: Option<&Rounding>,
+    ) -> Money {
+        let shopify = shopify_product.price;
+        let money = |cents: i64| Money::new(cents, shopify.currency());
+        let round = |price: Money| rounding.map_or(price, |r| r.round(price));
+        match self {
+            // Only a list price above the Shopify price is rounded, so rounding never raises a
+            // price the list price does not
+            PricingPolicy::RaiseOnly => {
+                let list = money(abc_product.list().cents());
+                if list.cents() > shopify.cents() {
+                    money(round(list).cents().max(shopify.cents()))
+                } else {
+                    shopify
+                }
+            }
+            PricingPolicy::ExactSync => round(money(abc_product.list().cents())),
+            // Without an ABC cost above 0 there is nothing to mark up, so keep the Shopify price
+            PricingPolicy::Markup { percent } => match abc_product.cost() {
+                Some(cost) if cost.cents() > 0 => round(money(
+                    (cost.cents() as f64 * (1.0 + percent / 100.0)).round() as i64,
+                )),
+                _ => shopify,
+            },
+            PricingPolicy::Fixed { price } => money(*price),
+        }
+    }
+}
+
+impl Default for DecreaseGuard {
+    fn default() -> Self {
+        DecreaseGuard {
+            max_percent: DEFAULT_MAX_DECREASE_PERCENT,
+            allow_skus: None,
+            deny_skus: Vec::new(),
+        }
+    }
+}
+
+impl DecreaseGuard {
+    /// Limit a price decrease to what the guard allows
+    ///
+    /// # Arguments
+    ///
+    /// * `sku` - The ABC SKU of the variant
+    /// * `before` - The current Shopify price
+    /// * `after` - The price the rule decided, in the same currency
+    ///
+    /// # Returns
+    ///
+    /// The allowed price, and why it differs from `after` if it does
+    pub fn limit(&self, sku: &str, before: Money, after: Money) -> (Money, Option<String>) {
+        if after.cents() >= before.cents() {
+            return (after, None);
+        }
+
+        let listed = |skus: &[String]| skus.iter().any(|s| s.eq_ignore_ascii_case(sku));
+        if listed(&self.deny_skus) {
+            return (before, Some(format!("SKU {} may not be lowered", sku)));
+        }
+        if let Some(allow) = &self.allow_skus {
+            if !listed(allow) {
+                return (
+                    before,
+                    Some(format!("SKU {} is not allowed to be lowered", sku)),
+                );
+            }
+        }
+
+        let floor = Money::new(
+            (before.cents() as f64 * (1.0 - self.max_percent.clamp(0.0, 100.0) / 100.0)).ceil()
+                as i64,
+            before.currency(),
+        );
+        if after.cents() < floor.cents() {
+            (
+                floor,
+                Some(format!(
+                    "decrease to {} is more than {}% so it is cut to {}",
+                    after, self.max_percent, floor
+                )),
+            )
+        } else {
+            (after, None)
+        }
+    }
+}
+
+impl MarginFloor {
+    /// Calculate the lowest price allowed for an ABC cost
+    ///
+    /// # Arguments
+    ///
+    /// * `cost` - The ABC cost
+    ///
+    /// # Returns
+    ///
+    /// The floor in the currency of `cost`, rounded up to the cent. `min_percent_over_cost` counts
+    /// to a hundredth of a percent
+    pub fn floor(&self, cost: Money) -> Money {
+        // In whole cents and hundredths of a percent, since a float like 1000 * 1.1 lands just
+        // above 1100 and would round up a cent
+        let hundredths = (self.min_percent_over_cost * 100.0).round() as i128;
+        let floor = cost.cents() as i128 * (10_000 + hundredths);
+        let cents = floor.div_euclid(10_000) + i128::from(floor.rem_euclid(10_000) > 0);
+        Money::new(
+            cents.clamp(i64::MIN as i128, i64::MAX as i128) as i64,
+            cost.currency(),
+        )
+    }
+}
+
+impl std::fmt::Display for PricingPolicy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PricingPolicy::RaiseOnly => write!(f, "raise only"),
+            PricingPolicy::ExactSync => write!(f, "exact sync"),
+            PricingPolicy::Markup { percent } => write!(f, "{}% markup over cost", percent),
+            PricingPolicy::Fixed { price } => write!(f, "fixed price {}", format_cents(*price)),
+        }
+    }
+}
+
+impl PricingRules {
+    /// Read a rule file
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The path to the JSON rule file
+    ///
+    /// # Errors
+    ///
+    /// Returns `FixerError::Custom` if the file cannot be read or a rule would set a price of 0 or
+    /// below, and `FixerError::SerdeJson` if it is not a valid rule file
+    pub fn read(path: &Path) -> Result<Self, FixerError> {
+        let rules_str = fs::read_to_string(path).or(Err(FixerError::Custom(format!(
+            "Could not read rule file {:?}",
+            path
+        ))))?;
+        let rules: PricingRules = serde_json::from_str(&rules_str)?;
+        for rule in rules.rules.iter() {
+            let invalid = match rule.policy {
+                PricingPolicy::Markup { percent } if !(percent.is_finite() && percent >= 0.0) => {
+                    Some(format!("a markup of {}%, which is below 0", percent))
+                }
+                PricingPolicy::Fixed { price } if price <= 0 => Some(format!(
+                    "a fixed price of {}, which is not above 0",
+                    format_cents(price)
+                )),
+                _ => None,
+            };
+            if let Some(invalid) = invalid {
+                return Err(FixerError::Custom(format!(
+                    "Rule {} in rule file {:?} has {}",
+                    rule.name, path, invalid
+                )));
+            }
+        }
+        Ok(rules)
+    }
+
+    /// Check a price against the `MarginFloor` over the ABC cost
+    ///
+    /// # Arguments
+    ///
+    /// * `price` - The price to check
+    /// * `abc_product` - The ABC product holding the cost
+    /// * `rounding` - How a price raised to the floor is rounded
+    ///
+    /// # Returns
+    ///
+    /// The price, raised to the floor if it is below it and the action is `FloorAction::Raise`,
+    /// and the floor if the price is below it
+    fn raise_to_floor(
+        &self,
+        price: Money,
+        abc_product: &AbcProduct,
+        rounding: Option<&Rounding>,
+    ) -> (Money, Option<(Money, FloorAction)>) {
+        let floor = self
+            .margin_floor
+            .as_ref()
+            .zip(abc_product.cost())
+            .map(|(m, cost)| (m, m.floor(Money::new(cost.cents(), price.currency()))));
+        match floor {
+            Some((m, floor)) if price.cents() < floor.cents() => {
+                let price = match m.action {
+                    // Keep the price ending when rounding does not take it back under the floor
+                    FloorAction::Raise => {
+                        let rounded = rounding.map_or(floor, |r| r.round(floor));
+                        if rounded.cents() < floor.cents() {
+                            floor
+                        } else {
+                            rounded
+                        }
+                    }
+                    FloorAction::Flag => price,
+                };
+                (price, Some((floor, m.action)))
+            }
+            _ => (price, None),
+        }
+    }
+
+    /// Decide the price of a variant with the first rule that matches it. Variants no rule
+    /// matches are only ever raised to the ABC list price. The `SalePolicy` decides between that
+    /// regular price, an ABC promo price and an existing Shopify sale. A running scheduled
+    /// promotion sets the price, and once it ends the regular price is put back if the variant
+    /// still has the promotion price. Then decreases other than scheduled promotions are limited
+    /// by the `DecreaseGuard`, and prices below the `MarginFloor` are raised or flagged, sale
+    /// prices included. No price is ever below the MAP of the variant
+    ///
+    /// # Arguments
+    ///
+    /// * `shopify_product` - The Shopify variant
+    /// * `abc_product` - The ABC product matched to the variant
+    /// * `promotion` - The status of the scheduled promotions for the product, if it has any
+    /// * `map_price` - The minimum advertised price of the variant, if it has one
+    ///
+    /// # Returns
+    ///
+    /// The `PriceDecision` holding the rule that matched and the price it decided
+    pub fn decide(
+        &self,
+        shopify_product: &ShopifyProduct,
+        abc_product: &AbcProduct,
+        promotion: Option<PromotionStatus<'_>>,
+        map_price: Option<Money>,
+    ) -> PriceDecision {
+        let (rule, policy, rounding) = match self
+            .rules
+            .iter()
+            .find(|r| r.matches(shopify_product, abc_product))
+        {
+            Some(r) => (
+                r.name.to_owned(),
+                r.policy.to_owned(),
+                r.rounding.as_ref().or(self.rounding.as_ref()),
+            ),
+            None => (
+                DEFAULT_RULE.to_string(),
+                PricingPolicy::RaiseOnly,
+                self.rounding.as_ref(),
+            ),
+        };
+        let rounding = match policy {
+            PricingPolicy::Fixed { .. } => None,
+            _ => rounding.cloned(),
+        };
+        let currency = shopify_product.price.currency();
+        let money = |cents: i64| Money::new(cents, currency);
+        let regular = policy.price(shopify_product, abc_product, rounding.as_ref());
+        let unusable_cost = match policy {
+            PricingPolicy::Markup { .. } => abc_product
+                .cost()
+                .map(|c| money(c.cents()))
+                .filter(|c| c.cents() <= 0),
+            _ => None,
+        };
+
+        let mut compare_at_price = None;
+        let mut promo = None;
+        let mut ended_promotion = None;
+        let mut kept_sale = false;
+        let mut scheduled = false;
+        let on_sale = shopify_product
+            .compare_at_price
+            .is_some_and(|c| c.cents() > shopify_product.price.cents());
+        let mut price = match (promotion, abc_product.promo().map(|p| money(p.cents()))) {
+            (Some(PromotionStatus::Active(p)), _) if p.price.cents() < regular.cents() => {
+                compare_at_price = Some(Some(regular));
+                promo = Some(p.to_string());
+                scheduled = true;
+                money(p.price.cents())
+            }
+            (_, Some(p)) if self.sale.promo_prices && p.cents() < regular.cents() => {
+                compare_at_price = Some(Some(regular));
+                promo = Some(format!("ABC promo price {}", p));
+                p
+            }
+            // The promotion stays in the file after it ends, so only the sale it set is ended, at
+            // its price or at that price raised to the margin floor and MAP. A later sale, or a
+            // price changed by hand, is left to the `SalePolicy`
+            (Some(PromotionStatus::Ended(p)), _)
+                if on_sale
+                    && (shopify_product.price.cents() == p.price.cents()
+                        || shopify_product.price.cents()
+                            == self
+                                .raise_to_floor(
+                                    money(p.price.cents()),
+                                    abc_product,
+                                    rounding.as_ref(),
+                                )
+                                .0
+                                .cents()
+                                .max(map_price.map_or(0, |m| m.cents()))) =>
+            {
+                compare_at_price = Some(None);
+                ended_promotion = Some(p.to_string());
+                regular
+            }
+            // ABC decides which variants are on sale, so a sale without an ABC promo price is over
+            (_, None) if on_sale && self.sale.promo_prices => {
+                compare_at_price = Some(None);
+                ended_promotion = Some("ABC promo".to_string());
+                regular
+            }
+            _ if on_sale => match self.sale.on_sale {
+                OnSale::Keep => {
+                    kept_sale = true;
+                    shopify_product.price
+                }
+                OnSale::Reprice => regular,
+                OnSale::End => {
+                    compare_at_price = Some(None);
+                    regular
+                }
+            },
+            _ => regular,
+        };
+
+        // ABC promo prices are held to the same limits as regular prices, so a corrupt promo price
+        // cannot cut a price further than any rule could. A scheduled promotion is set by hand, so
+        // it skips the `DecreaseGuard` and starts and ends at its own price. A sale that is kept
+        // is left alone
+        let mut guarded = None;
+        let mut below_floor = None;
+        if !kept_sale {
+            if !scheduled {
+                (price, guarded) =
+                    self.decreases
+                        .limit(&abc_product.sku(), shopify_product.price, price);
+            }
+            (price, below_floor) = self.raise_to_floor(price, abc_product, rounding.as_ref());
+        }
+        // A sale price raised to the regular price is no sale
+        if compare_at_price.is_some_and(|c| c.is_some_and(|c| price.cents() >= c.cents())) {
+            compare_at_price = None;
+            promo = None;
+        }
+
+        // MAP is a hard floor on everything, including sale prices and prices that are kept
+        let below_map = match map_price.map(|m| money(m.cents())) {
+            Some(m) if price.cents() < m.cents() => {
+                price = m;
+                if compare_at_price.is_some_and(|c| c.is_some_and(|c| c.cents() <= m.cents())) {
+                    compare_at_price = None;
+                    promo = None;
+                }
+                kept_sale = false;
+                Some(m)
+            }
+            _ => None,
+        };
+
+        PriceDecision {
+            price,
+            guarded,
+            below_floor,
+            rule,
+            policy,
+            rounding,
+            compare_at_price,
+            promo,
+            ended_promotion,
+            kept_sale,
+            below_map,
+            unusable_cost,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::money::{Currency, Money};
+    use crate::product::AbcProductBuilder;
+    use crate::promotions::Promotion;
+    use std::collections::HashMap;
+
+    fn usd(cents: i64) -> Money {
+        Money::new(cents, Currency::USD)
+    }
+
+    fn shopify(price: i64, compare_at_price: Option<i64>) -> ShopifyProduct {
+        ShopifyProduct {
+            id: "gid://shopify/ProductVariant/1".to_string(),
+            sku: "A1".to_string(),
+            display_name: "Item".to_string(),
+            price: usd(price),
+            compare_at_price: compare_at_price.map(usd),
+            barcode: None,
+            available_for_sale: true,
+            inventory_item_id: "gid://shopify/InventoryItem/1".to_string(),
+            cost: None,
+            stock: HashMap::new(),
+            market_prices: HashMap::new(),
+            product_id: "gid://shopify/Product/1".to_string(),
+            is_active: true,
+            vendor: String::new(),
+            product_type: String::new(),
+            tags: Vec::new(),
+        }
+    }
+
+    fn abc(list: i64, cost: i64, promo: Option<i64>) -> AbcProduct {
+        let builder = AbcProductBuilder::new()
+            .with_sku("A1")
+            .with_desc("Item")
+            .with_list(usd(list))
+            .with_cost(usd(cost))
+            .with_stock(1.0);
+        match promo {
+            Some(p) => builder.with_promo(usd(p)),
+            None => builder,
+        }
+        .build()
+        .unwrap()
+    }
+
+    fn rule(policy: PricingPolicy) -> PricingRule {
+        PricingRule {
+            name: "Everything".to_string(),
+            sku_prefix: None,
+            vendor: None,
+            product_type: None,
+            tag: None,
+            rounding: None,
+            policy,
+        }
+    }
+
+    /// Write a rule file to a temporary path and read it back
+    fn read_rules(name: &str, json: &str) -> Result<PricingRules, FixerError> {
+        let path = std::env::temp_dir().join(format!(
+            "shopify-price-fixer-{}-{}.json",
+            std::process::id(),
+            name
+        ));
+        std::fs::write(&path, json).unwrap();
+        PricingRules::read(&path)
+    }
+
+    fn no_decrease_limit() -> DecreaseGuard {
+        DecreaseGuard {
+            max_percent: 100.0,
+            ..Default::default()
+        }
+    }
+
+    fn promo_rules() -> PricingRules {
+        PricingRules {
+            sale: SalePolicy {
+                promo_prices: true,
+                ..Default::default()
+            },
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn promo_price_is_raised_to_the_margin_floor() {
+        let rules = PricingRules {
+            decreases: no_decrease_limit(),
+            margin_floor: Some(MarginFloor {
+                min_percent_over_cost: 25.0,
+                action: FloorAction::Raise,
+            }),
+            ..promo_rules()
+        };
+        let decision = rules.decide(&shopify(1299, None), &abc(1299, 800, Some(500)), None, None);
+        assert_eq!(decision.price, usd(1000));
+        assert_eq!(decision.below_floor, Some((usd(1000), FloorAction::Raise)));
+        assert_eq!(decision.compare_at_price, Some(Some(usd(1299))));
+    }
+
+    #[test]
+    fn decreases_are_limited_by_default() {
+        let rules = PricingRules {
+            rules: vec![rule(PricingPolicy::ExactSync)],
+            ..Default::default()
+        };
+        let decision = rules.decide(&shopify(1000, None), &abc(100, 50, None), None, None);
+        assert_eq!(decision.price, usd(800));
+        assert!(decision.guarded.is_some());
+    }
+
+    #[test]
+    fn rule_file_without_decreases_limits_them() {
+        let rules: PricingRules =
+            serde_json::from_str(r#"{ "decreases": { "deny_skus": ["C3"] } }"#).unwrap();
+        assert_eq!(rules.decreases.max_percent, DEFAULT_MAX_DECREASE_PERCENT);
+        let rules: PricingRules = serde_json::from_str("{}").unwrap();
+        assert_eq!(rules.decreases.max_percent, DEFAULT_MAX_DECREASE_PERCENT);
+    }
+
+    #[test]
+    fn promo_price_cut_is_limited_by_the_decrease_guard() {
+        let decision =
+            promo_rules().decide(&shopify(1000, None), &abc(1000, 100, Some(100)), None, None);
+        assert_eq!(decision.price, usd(800));
+        assert!(decision.guarded.is_some());
+        assert_eq!(decision.compare_at_price, Some(Some(usd(1000))));
+    }
+
+    #[test]
+    fn promo_price_raised_to_the_regular_price_is_no_sale() {
+        let rules = PricingRules {
+            margin_floor: Some(MarginFloor {
+                min_percent_over_cost: 50.0,
+                action: FloorAction::Raise,
+            }),
+            ..promo_rules()
+        };
+        let decision = rules.decide(&shopify(1000, None), &abc(1000, 800, Some(500)), None, None);
+        assert_eq!(decision.price, usd(1200));
+        assert_eq!(decision.compare_at_price, None);
+        assert_eq!(decision.promo, None);
+    }
+
+    fn promotion(price: i64) -> Promotion {
+        let date = |d: &str| chrono::NaiveDateTime::parse_from_str(d, "%Y-%m-%d %H:%M").unwrap();
+        Promotion {
+            sku: Some("A1".to_string()),
+            upc: None,
+            price: usd(price),
+            start: date("2024-10-01 00:00"),
+            end: date("2024-10-07 23:59"),
+        }
+    }
+
+    #[test]
+    fn promotion_price_is_held_to_the_margin_floor() {
+        let rules = PricingRules {
+            decreases: no_decrease_limit(),
+            margin_floor: Some(MarginFloor {
+                min_percent_over_cost: 0.0,
+                action: FloorAction::Raise,
+            }),
+            ..Default::default()
+        };
+        let promotion = promotion(300);
+        let decision = rules.decide(
+            &shopify(1000, None),
+            &abc(1000, 500, None),
+            Some(PromotionStatus::Active(&promotion)),
+            None,
+        );
+        assert_eq!(decision.price, usd(500));
+        assert_eq!(decision.compare_at_price, Some(Some(usd(1000))));
+    }
+
+    #[test]
+    fn ended_promotion_puts_back_the_regular_price() {
+        let promotion = promotion(700);
+        let decision = PricingRules::default().decide(
+            &shopify(700, Some(1000)),
+            &abc(1000, 500, None),
+            Some(PromotionStatus::Ended(&promotion)),
+            None,
+        );
+        assert_eq!(decision.price, usd(1000));
+        assert_eq!(decision.compare_at_price, Some(None));
+        assert!(decision.ended_promotion.is_some());
+    }
+
+    #[test]
+    fn promotion_price_skips_the_decrease_guard() {
+        let promotion = promotion(700);
+        let decision = PricingRules::default().decide(
+            &shopify(1000, None),
+            &abc(1000, 500, None),
+            Some(PromotionStatus::Active(&promotion)),
+            None,
+        );
+        assert_eq!(decision.price, usd(700));
+        assert_eq!(decision.guarded, None);
+        assert_eq!(decision.compare_at_price, Some(Some(usd(1000))));
+    }
+
+    #[test]
+    fn promotion_raised_to_the_margin_floor_ends() {
+        let rules = PricingRules {
+            margin_floor: Some(MarginFloor {
+                min_percent_over_cost: 0.0,
+                action: FloorAction::Raise,
+            }),
+            ..Default::default()
+        };
+        let promotion = promotion(500);
+        let abc_product = abc(1000, 800, None);
+        let active = rules.decide(
+            &shopify(1000, None),
+            &abc_product,
+            Some(PromotionStatus::Active(&promotion)),
+            None,
+        );
+        assert_eq!(active.price, usd(800));
+
+        let ended = rules.decide(
+            &shopify(active.price.cents(), Some(1000)),
+            &abc_product,
+            Some(PromotionStatus::Ended(&promotion)),
+            None,
+        );
+        assert_eq!(ended.price, usd(1000));
+        assert_eq!(ended.compare_at_price, Some(None));
+        assert!(ended.ended_promotion.is_some());
+    }
+
+    #[test]
+    fn promotion_raised_to_map_ends() {
+        let promotion = promotion(500);
+        let ended = PricingRules::default().decide(
+            &shopify(900, Some(1000)),
+            &abc(1000, 300, None),
+            Some(PromotionStatus::Ended(&promotion)),
+            Some(usd(900)),
+        );
+        assert_eq!(ended.price, usd(1000));
+        assert!(ended.ended_promotion.is_some());
+    }
+
+    #[test]
+    fn ended_promotion_leaves_a_later_sale_alone() {
+        let promotion = promotion(700);
+        let decision = PricingRules::default().decide(
+            &shopify(800, Some(1000)),
+            &abc(1000, 500, None),
+            Some(PromotionStatus::Ended(&promotion)),
+            None,
+        );
+        assert_eq!(decision.price, usd(800));
+        assert!(decision.kept_sale);
+        assert_eq!(decision.ended_promotion, None);
+    }
+
+    #[test]
+    fn sale_ends_once_the_abc_promo_is_gone() {
+        let decision =
+            promo_rules().decide(&shopify(800, Some(1000)), &abc(1000, 500, None), None, None);
+        assert_eq!(decision.price, usd(1000));
+        assert_eq!(decision.compare_at_price, Some(None));
+        assert!(!decision.kept_sale);
+        assert!(decision.ended_promotion.is_some());
+    }
+
+    #[test]
+    fn sale_is_kept_without_promo_prices() {
+        let decision = PricingRules::default().decide(
+            &shopify(800, Some(1000)),
+            &abc(1000, 500, None),
+            None,
+            None,
+        );
+        assert_eq!(decision.price, usd(800));
+        assert_eq!(decision.compare_at_price, None);
+        assert!(decision.kept_sale);
+    }
+
+    #[test]
+    fn markup_keeps_the_price_without_a_cost_above_0() {
+        let rules = PricingRules {
+            rules: vec![rule(PricingPolicy::Markup { percent: 40.0 })],
+            ..Default::default()
+        };
+        let decision = rules.decide(&shopify(1000, None), &abc(1299, 0, None), None, None);
+        assert_eq!(decision.price, usd(1000));
+        assert_eq!(decision.unusable_cost, Some(usd(0)));
+
+        let decision = rules.decide(&shopify(600, None), &abc(1299, 500, None), None, None);
+        assert_eq!(decision.price, usd(700));
+        assert_eq!(decision.unusable_cost, None);
+    }
+
+    #[test]
+    fn decision_is_in_the_store_currency() {
+        let cad: Currency = "CAD".parse().unwrap();
+        let rules = PricingRules {
+            rules: vec![rule(PricingPolicy::Fixed { price: 1999 })],
+            ..Default::default()
+        };
+        let mut product = shopify(1000, None);
+        product.price = Money::new(1000, cad);
+        let decision = rules.decide(&product, &abc(1299, 500, None), None, None);
+        assert_eq!(decision.price, Money::new(1999, cad));
+    }
+
+    #[test]
+    fn rule_file_with_a_negative_markup_is_rejected() {
+        let json = r#"{ "rules": [{ "name": "Cheap", "policy": "markup", "percent": -150 }] }"#;
+        assert!(read_rules("negative-markup", json).is_err());
+    }
+
+    #[test]
+    fn rule_file_with_a_zero_fixed_price_is_rejected() {
+        let json = r#"{ "rules": [{ "name": "Free", "policy": "fixed", "price": "0.00" }] }"#;
+        assert!(read_rules("zero-fixed", json).is_err());
+        let json = r#"{ "rules": [{ "name": "Special", "policy": "fixed", "price": "19.99" }] }"#;
+        assert!(read_rules("fixed", json).is_ok());
+    }
+
+    #[test]
+    fn raise_only_keeps_a_higher_shopify_price_unrounded() {
+        let rules = PricingRules {
+            rounding: Some(Rounding::NinetyNine),
+            ..Default::default()
+        };
+        let decision = rules.decide(&shopify(1050, None), &abc(1000, 500, None), None, None);
+        assert_eq!(decision.price, usd(1050));
+
+        let decision = rules.decide(&shopify(900, None), &abc(1000, 500, None), None, None);
+        assert_eq!(decision.price, usd(1099));
+    }
+
+    #[test]
+    fn margin_floor_is_exact_in_cents() {
+        let floor = |min_percent_over_cost| MarginFloor {
+            min_percent_over_cost,
+            action: FloorAction::Raise,
+        };
+        assert_eq!(floor(10.0).floor(usd(1000)), usd(1100));
+        assert_eq!(floor(15.0).floor(usd(1999)), usd(2299));
+        assert_eq!(floor(12.5).floor(usd(800)), usd(900));
+        assert_eq!(floor(0.0).floor(usd(0)), usd(0));
+    }
+
+    #[test]
+    fn first_matching_rule_decides() {
+        let mut acme = rule(PricingPolicy::Fixed { price: 1999 });
+        acme.name = "Acme".to_string();
+        acme.vendor = Some("ACME".to_string());
+        acme.sku_prefix = Some("a".to_string());
+        let rules = PricingRules {
+            rules: vec![acme, rule(PricingPolicy::ExactSync)],
+            ..Default::default()
+        };
+
+        let mut variant = shopify(1000, None);
+        variant.vendor = "Acme".to_string();
+        let decision = rules.decide(&variant, &abc(1100, 500, None), None, None);
+        assert_eq!(decision.rule, "Acme");
+        assert_eq!(decision.price, usd(1999));
+
+        let decision = rules.decide(&shopify(1000, None), &abc(1100, 500, None), None, None);
+        assert_eq!(decision.rule, "Everything");
+        assert_eq!(decision.price, usd(1100));
+    }
+
+    #[test]
+    fn sale_policy_reprices_or_ends_a_sale() {
+        let on_sale = |on_sale| PricingRules {
+            rules: vec![rule(PricingPolicy::ExactSync)],
+            sale: SalePolicy {
+                on_sale,
+                promo_prices: false,
+            },
+            ..Default::default()
+        };
+        let variant = shopify(800, Some(1000));
+
+        let decision = on_sale(OnSale::Reprice).decide(&variant, &abc(900, 500, None), None, None);
+        assert_eq!(decision.price, usd(900));
+        assert_eq!(decision.compare_at_price, None);
+
+        let decision = on_sale(OnSale::End).decide(&variant, &abc(900, 500, None), None, None);
+        assert_eq!(decision.price, usd(900));
+        assert_eq!(decision.compare_at_price, Some(None));
+    }
+
+    #[test]
+    fn map_is_a_floor_on_every_price() {
+        let rules = PricingRules {
+            rules: vec![rule(PricingPolicy::Fixed { price: 500 })],
+            decreases: no_decrease_limit(),
+            ..Default::default()
+        };
+        let decision = rules.decide(
+            &shopify(1000, None),
+            &abc(1000, 300, None),
+            None,
+            Some(usd(799)),
+        );
+        assert_eq!(decision.price, usd(799));
+        assert_eq!(decision.below_map, Some(usd(799)));
+
+        let decision = PricingRules::default().decide(
+            &shopify(800, Some(1000)),
+            &abc(1000, 300, None),
+            None,
+            Some(usd(900)),
+        );
+        assert_eq!(decision.price, usd(900));
+        assert!(!decision.kept_sale);
+    }
+
+    #[test]
+    fn flagged_margin_floor_keeps_the_price() {
+        let rules = PricingRules {
+            rules: vec![rule(PricingPolicy::Fixed { price: 500 })],
+            decreases: no_decrease_limit(),
+            margin_floor: Some(MarginFloor {
+                min_percent_over_cost: 25.0,
+                action: FloorAction::Flag,
+            }),
+            ..Default::default()
+        };
+        let decision = rules.decide(&shopify(1000, None), &abc(1000, 480, None), None, None);
+        assert_eq!(decision.price, usd(500));
+        assert_eq!(decision.below_floor, Some((usd(600), FloorAction::Flag)));
+    }
+
+    #[test]
+    fn denied_and_unlisted_skus_are_not_lowered() {
+        let guard = |allow_skus: Option<Vec<String>>, deny_skus: Vec<String>| DecreaseGuard {
+            max_percent: 100.0,
+            allow_skus,
+            deny_skus,
+        };
+        let (price, why) = guard(None, vec!["a1".to_string()]).limit("A1", usd(1000), usd(500));
+        assert_eq!((price, why.is_some()), (usd(1000), true));
+        let (price, why) =
+            guard(Some(vec!["B2".to_string()]), Vec::new()).limit("A1", usd(1000), usd(500));
+        assert_eq!((price, why.is_some()), (usd(1000), true));
+        let (price, why) =
+            guard(Some(vec!["A1".to_string()]), Vec::new()).limit("A1", usd(1000), usd(500));
+        assert_eq!((price, why), (usd(500), None));
+        let (price, why) = guard(None, vec!["A1".to_string()]).limit("A1", usd(1000), usd(1200));
+        assert_eq!((price, why), (usd(1200), None));
+    }
+}
@@ -0,0 +1,146 @@
+use crate::product::ThrottleStatus;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// The cost to assume for a query document Shopify has not reported a cost for yet. Most single
+/// mutations cost this much
+const DEFAULT_QUERY_COST: f64 = 10.0;
+
+/// Tracks the leaky bucket Shopify uses to rate limit the GraphQL admin API, so requests can wait
+/// for enough points to be restored instead of being rejected as THROTTLED
+#[derive(Debug, Default)]
+pub struct Throttle {
+    state: Mutex<ThrottleState>,
+}
+
+#[derive(Debug, Default)]
+struct ThrottleState {
+    /// The last `ThrottleStatus` Shopify reported and when it was received. `None` until the first
+    /// response arrives
+    status: Option<(ThrottleStatus, Instant)>,
+
+    /// Points spent by requests sent since `status` was received
+    reserved: f64,
+
+    /// The requested cost Shopify last reported for each query document
+    costs: HashMap<String, f64>,
+}
+
+impl ThrottleState {
+    /// How many points are expected to be in the bucket right now
+    fn available(&self, now: Instant) -> Option<(f64, &ThrottleStatus)> {
+        let (status, received) = self.status.as_ref()?;
+        let restored = now.duration_since(*received).as_secs_f64() * status.restore_rate;
+        let available =
+            (status.currently_available + restored - self.reserved).min(status.maximum_available);
+        Some((available, status))
+    }
+}
+
+impl Throttle {
+    /// Wait until the bucket holds enough points to send `query`, then reserve them
+    ///
+    /// # Arguments
+    ///
+    /// * `query` - The GraphQL document about to be sent. Its cost is estimated from the last time
+    ///   the same document was sent
+    ///
+    /// # Returns
+    ///
+    /// The points reserved, to give back with `release` if the request fails before Shopify
+    /// reports its cost
+    pub async fn acquire(&self, query: &str) -> f64 {
+        loop {
+            let wait = {
+                let mut state = self.state.lock().expect("throttle lock is never poisoned");
+                let cost = *state.costs.get(query).unwrap_or(&DEFAULT_QUERY_COST);
+                let (available, status) = match state.available(Instant::now()) {
+                    Some(a) => a,
+                    None => return 0.0,
+                };
+
+                // A query can never cost more than the bucket holds, so never wait for more
+                let cost = cost.min(status.maximum_available);
+                if available >= cost {
+                    state.reserved += cost;
+                    return cost;
+                }
+                if status.restore_rate <= 0.0 {
+                    return 0.0;
+                }
+                Duration::from_secs_f64((cost - available) / status.restore_rate)
+            };
+            tokio::time::sleep(wait).await;
+        }
+    }
+
+    /// Record the cost Shopify reported in the `extensions` of a response
+    ///
+    /// # Arguments
+    ///
+    /// * `query` - The GraphQL document that was sent
+    /// * `requested_query_cost` - The cost Shopify calculated for `query` before running it
+    /// * `status` - The state of the bucket after running `query`
+    pub fn update(&self, query: &str, requested_query_cost: f64, status: ThrottleStatus) {
+        let mut state = self.state.lock().expect("throttle lock is never poisoned");
+        state.costs.insert(query.to_string(), requested_query_cost);
+        state.status = Some((status, Instant::now()));
+        state.reserved = 0.0;
+    }
+
+    /// Give back points reserved by `acquire` for a request that failed before Shopify reported
+    /// its cost, so they are not held until the next response
+    ///
+    /// # Arguments
+    ///
+    /// * `reserved` - The points `acquire` returned
+    pub fn release(&self, reserved: f64) {
+        let mut state = self.state.lock().expect("throttle lock is never poisoned");
+        state.reserved = (state.reserved - reserved).max(0.0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::product::Cost;
+
+    /// The cost Shopify reports with a THROTTLED error, with floats and no actual cost
+    const THROTTLED_COST: &str = r#"{
+        "requestedQueryCost": 752,
+        "actualQueryCost": null,
+        "throttleStatus": {
+            "maximumAvailable": 2000.0,
+            "currentlyAvailable": 120.0,
+            "restoreRate": 100.0
+        }
+    }"#;
+
+    #[test]
+    fn parses_shopify_cost_with_floats_and_no_actual_cost() {
+        let cost: Cost = serde_json::from_str(THROTTLED_COST).unwrap();
+        assert_eq!(cost.requested_query_cost, 752.0);
+        assert_eq!(cost.actual_query_cost, None);
+        assert_eq!(cost.throttle_status.maximum_available, 2000.0);
+        assert_eq!(cost.throttle_status.restore_rate, 100.0);
+    }
+
+    #[tokio::test]
+    async fn release_gives_back_reserved_points() {
+        let throttle = Throttle::default();
+        let cost: Cost = serde_json::from_str(THROTTLED_COST).unwrap();
+        throttle.update("query", 100.0, cost.throttle_status);
+
+        let reserved = throttle.acquire("query").await;
+        assert_eq!(reserved, 100.0);
+        let available = |t: &Throttle| {
+            let state = t.state.lock().unwrap();
+            state.available(state.status.as_ref().unwrap().1).unwrap().0
+        };
+        assert_eq!(available(&throttle), 20.0);
+
+        throttle.release(reserved);
+        assert_eq!(available(&throttle), 120.0);
+    }
+}
@@ -14,7 +14,7 @@ impl std::fmt::Display for UpcError {
 
 impl std::error::Error for UpcError {}
 
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Deserialize)]
 pub struct Upc {
     upc: [u8; 12],
 }
@@ -55,14 +55,14 @@ impl Upc {
         let mut fixed = [0u8; 12];
         let mut sum1 = 0;
         let mut sum2 = 0;
-        for i in 0..11 {
+        for (i, slot) in fixed.iter_mut().take(11).enumerate() {
             let digit = upc.get(i)?;
             if i % 2 == 0 {
                 sum1 += digit;
             } else {
                 sum2 += digit;
             }
-            fixed[i] = *digit;
+            *slot = *digit;
         }
         let sum_total = sum1 * 3 + sum2;
         let mut checkd = 10 - sum_total % 10;
@@ -74,7 +74,7 @@ impl Upc {
     }
 
     pub fn from_abc_upc_list(abc_upc_list: &str) -> Vec<Option<Upc>> {
-        abc_upc_list.split(",").map(|s| Upc::normalize(s)).collect()
+        abc_upc_list.split(",").map(Upc::normalize).collect()
     }
 
     pub fn try_from_str_like<S>(string_like: S) -> Result<Upc, UpcError>
@@ -104,10 +104,10 @@ impl Upc {
     }
 }
 
-impl ToString for Upc {
-    fn to_string(&self) -> String {
+impl std::fmt::Display for Upc {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         let bytes = self.upc;
-        bytes.map(|c| c.to_string()).join("")
+        write!(f, "{}", bytes.map(|c| c.to_string()).join(""))
     }
 }
 